@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::{Grid, TopologyKind};
+use crate::arch::node::NodeParams;
+use crate::arch::router::{RouterPipeline, VirtualChannels};
+use crate::arch::routing::RoutingAlgorithm;
+use crate::arch::topology::LinkParams;
+use crate::comm::packet::Event;
+use crate::comm::transfer::Direction;
+use crate::sim::config::{ConfigError, check, check_link, check_node, check_vcs};
+use crate::sim::engine::Cycle;
+
+// Collects everything a grid is built from and builds it in one go
+// - Every option starts from the default the grid setters start from, build checks them all and
+// returns a ConfigError naming the option instead of a grid that panics once it runs
+// - Field names in the errors are the names of the options, per node and per link ones carry the
+// position, like node_params_at[(1, 2, 0)].tx_rate
+#[derive(Clone)]
+pub struct GridBuilder {
+    width: Dim,
+    height: Dim,
+    depth: Dim,
+    kind: TopologyKind,
+    node_params: NodeParams,
+    node_overrides: HashMap<Coord, NodeParams>,
+    planar_link: LinkParams,
+    vertical_link: LinkParams,
+    link_overrides: HashMap<(Coord, Direction), LinkParams>,
+    pipeline: Option<RouterPipeline>,
+    vcs: VirtualChannels,
+    deadlock_check: Option<Cycle>,
+    sample_interval: Option<Cycle>,
+    routing: Option<Arc<dyn RoutingAlgorithm>>,
+    event_tx: Option<UnboundedSender<Event>>,
+}
+
+impl GridBuilder {
+    pub fn new(width: Dim, height: Dim) -> Self {
+        Self {
+            width,
+            height,
+            depth: 1,
+            kind: TopologyKind::default(),
+            node_params: NodeParams::default(),
+            node_overrides: HashMap::new(),
+            planar_link: LinkParams::default(),
+            vertical_link: LinkParams::default(),
+            link_overrides: HashMap::new(),
+            pipeline: None,
+            vcs: VirtualChannels::default(),
+            deadlock_check: None,
+            sample_interval: None,
+            routing: None,
+            event_tx: None,
+        }
+    }
+
+    // Layers stacked on top of each other, see Grid::init_grid_3d
+    pub fn depth(mut self, depth: Dim) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn topology(mut self, kind: TopologyKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    // Rates and buffer depths of every node without params of its own
+    pub fn node_params(mut self, params: NodeParams) -> Self {
+        self.node_params = params;
+        self
+    }
+
+    // Flits per virtual channel at the far end of every link and inside every node
+    pub fn buffers(mut self, link_buffer: usize, inner_buffer: usize) -> Self {
+        self.node_params.link_buffer = link_buffer;
+        self.node_params.inner_buffer = inner_buffer;
+        self
+    }
+
+    // Flits every node sends and receives per service
+    pub fn node_rates(mut self, tx_rate: u64, rx_rate: u64) -> Self {
+        self.node_params.tx_rate = tx_rate;
+        self.node_params.rx_rate = rx_rate;
+        self
+    }
+
+    // The node at pos alone, wins over node_params, buffers and node_rates
+    pub fn node_params_at(mut self, pos: impl Into<Coord>, params: NodeParams) -> Self {
+        self.node_overrides.insert(pos.into(), params);
+        self
+    }
+
+    // Links inside a layer
+    pub fn link_params(mut self, params: LinkParams) -> Self {
+        self.planar_link = params;
+        self
+    }
+
+    // Links between layers
+    pub fn vertical_link_params(mut self, params: LinkParams) -> Self {
+        self.vertical_link = params;
+        self
+    }
+
+    // The link leaving pos towards dir alone, wins over the two above
+    pub fn link_params_at(
+        mut self,
+        pos: impl Into<Coord>,
+        dir: Direction,
+        params: LinkParams,
+    ) -> Self {
+        self.link_overrides.insert((pos.into(), dir), params);
+        self
+    }
+
+    pub fn router_pipeline(mut self, pipeline: Option<RouterPipeline>) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    pub fn virtual_channels(mut self, vcs: VirtualChannels) -> Self {
+        self.vcs = vcs;
+        self
+    }
+
+    pub fn deadlock_check(mut self, interval: Option<Cycle>) -> Self {
+        self.deadlock_check = interval;
+        self
+    }
+
+    pub fn sample_interval(mut self, interval: Option<Cycle>) -> Self {
+        self.sample_interval = interval;
+        self
+    }
+
+    // Hop by hop routing of a 2D mesh, see Grid::set_routing, build fails with it on anything else
+    pub fn routing(mut self, routing: impl RoutingAlgorithm + 'static) -> Self {
+        self.routing = Some(Arc::new(routing));
+        self
+    }
+
+    // Events go to event_tx instead of a channel of their own, the receiver build returns is then
+    // closed
+    // - Lets several grids or other producers share one receiver
+    pub fn event_sink(mut self, event_tx: UnboundedSender<Event>) -> Self {
+        self.event_tx = Some(event_tx);
+        self
+    }
+
+    // Checks every option and builds the grid, ready to take packets and run
+    pub fn build(self) -> Result<(Grid, UnboundedReceiver<Event>), ConfigError> {
+        self.validate()?;
+
+        let mut grid = Grid::default();
+        grid.set_topology(self.kind);
+        grid.set_node_params(self.node_params);
+        for (&pos, &params) in &self.node_overrides {
+            grid.set_node_params_at(pos, params);
+        }
+        grid.set_link_params(self.planar_link);
+        grid.set_vertical_link_params(self.vertical_link);
+        for (&(pos, dir), &params) in &self.link_overrides {
+            grid.set_link_params_at(pos, dir, params);
+        }
+        grid.set_router_pipeline(self.pipeline);
+        grid.set_virtual_channels(self.vcs);
+        grid.set_deadlock_check(self.deadlock_check);
+        grid.set_sample_interval(self.sample_interval);
+        if let Some(routing) = self.routing {
+            grid.set_shared_routing(routing);
+        }
+
+        let event_rx = grid.init_grid_3d(self.width, self.height, self.depth)?;
+        if let Some(event_tx) = self.event_tx {
+            grid.set_event_sink(event_tx);
+        }
+
+        Ok((grid, event_rx))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        check(self.width > 0, "width", "must be at least 1")?;
+        check(self.height > 0, "height", "must be at least 1")?;
+        check(self.depth > 0, "depth", "must be at least 1")?;
+        let inside = |at: Coord| at.x < self.width && at.y < self.height && at.z < self.depth;
+
+        check_node(self.node_params, "node_params")?;
+        for (&at, &params) in sorted(&self.node_overrides, |&at| at) {
+            let field = format!("node_params_at[{at}]");
+            check(inside(at), &field, "is outside the grid")?;
+            check_node(params, &field)?;
+        }
+
+        check_link(&self.planar_link, "link_params")?;
+        check_link(&self.vertical_link, "vertical_link_params")?;
+        // The wiring is only known to a grid, an empty one with the same shape answers for it
+        let mut shape = Grid::default();
+        shape.set_topology(self.kind);
+        shape.set_dimensions(self.width, self.height, self.depth);
+        for (&(at, dir), params) in sorted(&self.link_overrides, |&(at, dir)| {
+            (at, Direction::PORTS.iter().position(|&port| port == dir))
+        }) {
+            let field = format!("link_params_at[{at} {dir:?}]");
+            check(inside(at), &field, "is outside the grid")?;
+            check(
+                shape.neighbour(at, dir).is_some(),
+                &field,
+                "has no link in that direction",
+            )?;
+            check_link(params, &field)?;
+        }
+
+        check_vcs(self.vcs, "virtual_channels", "virtual_channels.selection")?;
+        check(
+            self.deadlock_check != Some(0),
+            "deadlock_check",
+            "must be at least 1 cycle",
+        )?;
+        check(
+            self.sample_interval != Some(0),
+            "sample_interval",
+            "must be at least 1 cycle",
+        )?;
+        check(
+            self.routing.is_none() || (self.kind == TopologyKind::Mesh && self.depth == 1),
+            "routing",
+            "only applies to a 2D mesh, a torus or stacked mesh uses source routes",
+        )
+    }
+}
+
+// Same order every time so the first invalid option reported doesn't depend on the hasher
+fn sorted<K, V, O: Ord>(map: &HashMap<K, V>, order: impl Fn(&K) -> O) -> Vec<(&K, &V)> {
+    let mut entries: Vec<(&K, &V)> = map.iter().collect();
+    entries.sort_by_key(|(key, _)| order(key));
+    entries
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// Type of a single coordinate, u16 allows up to 65536 nodes per side and the wide-coords feature
+// switches to u32 for anything bigger
+#[cfg(not(feature = "wide-coords"))]
+pub type Dim = u16;
+#[cfg(feature = "wide-coords")]
+pub type Dim = u32;
+
+// Position of a node, z is the layer in a stacked mesh and stays 0 for a 2D grid
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize, Debug,
+)]
+pub struct Coord {
+    pub x: Dim,
+    pub y: Dim,
+    pub z: Dim,
+}
+
+impl Coord {
+    pub fn new(x: Dim, y: Dim, z: Dim) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl From<(Dim, Dim)> for Coord {
+    fn from((x, y): (Dim, Dim)) -> Self {
+        Self { x, y, z: 0 }
+    }
+}
+
+impl From<(Dim, Dim, Dim)> for Coord {
+    fn from((x, y, z): (Dim, Dim, Dim)) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl fmt::Display for Coord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.x, self.y, self.z)
+    }
+}
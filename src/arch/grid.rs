@@ -1,212 +1,470 @@
 use std::collections::HashMap;
+use std::io;
 use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::mpsc::{self, UnboundedReceiver};
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-use crate::arch::node::MeshNode;
+use crate::arch::builder::GridBuilder;
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::node::{MeshNode, NodeParams};
+use crate::arch::router::{RouterPipeline, VirtualChannels};
+use crate::arch::routing::{
+    NegativeFirst, NorthLast, OddEven, RoutingAlgorithm, WestFirst, preferred_path,
+};
+use crate::arch::topology::{Link, LinkParams, NodeId, PortId, Topology};
 use crate::comm::packet::{Event, Packet};
 use crate::comm::transfer::NodeCommError;
-use crate::comm::transfer::{calc_path, receive_packets, send_packet};
+use crate::comm::transfer::{Direction, PORT_COUNT, calc_path_3d, calc_torus_path};
+use crate::export::topology;
+use crate::sim::config::{Config, ConfigError, RoutingKind};
+use crate::sim::deadlock::Deadlock;
+use crate::sim::engine::Cycle;
+use crate::sim::monitor::{LinkUsage, TimeSeries};
+use crate::sim::network::Network;
 
-const LINK_BUFFER_SIZE: usize = 2;
-const INNER_BUFFER_SIZE: usize = 4;
+// Per virtual channel, in flits
+pub const LINK_BUFFER_SIZE: usize = 2;
+pub const INNER_BUFFER_SIZE: usize = 4;
+// Flits per service through the receive and send stages of a node
+pub const NODE_RATE: u64 = 5;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum GridAccessError {
     #[error("Invalid width, error accessing column: {0}")]
-    InvalidWidth(u8),
+    InvalidWidth(Dim),
 
     #[error("Invalid height, error accessing row: {0}")]
-    InvalidHeight(u8),
+    InvalidHeight(Dim),
+
+    #[error("Invalid depth, error accessing layer: {0}")]
+    InvalidDepth(Dim),
+
+    #[error("Invalid node, no node at {0}")]
+    InvalidNode(Coord),
+
+    #[error("Invalid node, no node with id {0}")]
+    InvalidNodeId(NodeId),
+
+    #[error("Invalid node count, more nodes than coordinates: {0}")]
+    TooManyNodes(usize),
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyKind {
+    // Open 2D mesh, edge nodes have no links towards the outside
+    #[default]
+    Mesh,
+    // 2D mesh whose edge links wrap around to the opposite edge
+    // - Without virtual channels the rings can deadlock under heavy load
+    // - A side of a single node has no wrap links, so width x 1 is one ring
+    // - Stacked layers only wrap in x and y, the vertical links never wrap
+    Torus,
 }
 
 // Should figure out how to align each MeshNode at 64 byte boundary to avoid false sharing
+// - The grid is the mesh family of topologies, it describes the wiring and owns the network that
+// runs on top of it
 #[derive(Default)]
 pub struct Grid {
-    // nodes: Vec<Vec<Arc<MeshNode>>>,
-    nodes: Arc<[Arc<[MeshNode]>]>,
-    packet_count: usize,
+    width: Dim,
+    height: Dim,
+    depth: Dim,
+    kind: TopologyKind,
+    pipeline: Option<RouterPipeline>,
+    vcs: VirtualChannels,
+    deadlock_check: Option<Cycle>,
+    sample_interval: Option<Cycle>,
+    planar_link: LinkParams,
+    node_params: NodeParams,
+    // Nodes that differ from node_params
+    node_overrides: HashMap<Coord, NodeParams>,
+    // Through silicon vias between the layers of a stacked mesh
+    vertical_link: LinkParams,
+    // Links that differ from the two above, by the node they leave and the direction they go
+    link_overrides: HashMap<(Coord, Direction), LinkParams>,
+    // None falls back to negative first
+    routing: Option<Arc<dyn RoutingAlgorithm>>,
+    network: Network,
 }
 
 impl Grid {
-    // Need a robust way to build a grid of nodes of dimensions width x height
-    // - How will we handle instantiating the channels?
-    // - Maybe doing a transmit pass where we build all the channels and store the transmitters in
-    // the structs and store the receivers in a map based on coordinate, then a receive pass that
-    // checks the map for each node and provides the receivers
+    // Builds a grid of nodes of dimensions width x height
+    // - Only the setters called before it apply, Grid::builder takes every option and checks them
     pub fn init_grid(
         &mut self,
-        width: u8,
-        height: u8,
+        width: Dim,
+        height: Dim,
     ) -> Result<UnboundedReceiver<Event>, GridAccessError> {
-        // Collection to hold rx channels for second connection pass
-        #[derive(Default)]
-        struct ChannelHolder {
-            rx_up: Option<Receiver<Packet>>,
-            rx_down: Option<Receiver<Packet>>,
-            rx_left: Option<Receiver<Packet>>,
-            rx_right: Option<Receiver<Packet>>,
-            tx_up: Option<Sender<Packet>>,
-            tx_down: Option<Sender<Packet>>,
-            tx_left: Option<Sender<Packet>>,
-            tx_right: Option<Sender<Packet>>,
+        self.init_grid_3d(width, height, 1)
+    }
+
+    // Builds depth layers of width x height nodes stacked on top of each other, every node is
+    // linked to the nodes directly above and below it
+    pub fn init_grid_3d(
+        &mut self,
+        width: Dim,
+        height: Dim,
+        depth: Dim,
+    ) -> Result<UnboundedReceiver<Event>, GridAccessError> {
+        self.set_dimensions(width, height, depth);
+
+        let (mut network, event_rx) = Network::new(self, self.pipeline);
+        for node in 0..self.node_count() {
+            let params = self.node_overrides.get(&self.coord(node));
+            network.set_node_params(node, params.copied().unwrap_or(self.node_params));
         }
-        let mut rx_local_map: HashMap<(u8, u8), Receiver<Packet>> = HashMap::new();
-        let mut channel_map: HashMap<(u8, u8), ChannelHolder> = HashMap::new();
-        let (event_tx, event_rx) = mpsc::unbounded_channel::<Event>();
-
-        let mut temp_nodes: Vec<Vec<MeshNode>> = Vec::new();
-        for y in 0..height {
-            let mut grid_row = Vec::new();
-
-            for x in 0..width {
-                let (tx_local, rx_local) = mpsc::channel::<Packet>(INNER_BUFFER_SIZE);
-                let cur_node = MeshNode::init_channeless(x, y, 5, 5, tx_local);
-                rx_local_map.insert((x, y), rx_local);
-
-                // Create channels based on position
-                if y < height {
-                    let (tx_down, rx_down) = mpsc::channel::<Packet>(LINK_BUFFER_SIZE);
-
-                    let cur_node = channel_map.entry((x, y)).or_default();
-                    cur_node.tx_down = Some(tx_down);
-                    let up_node = channel_map.entry((x, y + 1)).or_default();
-                    up_node.rx_up = Some(rx_down);
-                }
-                if y > 0 {
-                    let (tx_up, rx_up) = mpsc::channel::<Packet>(LINK_BUFFER_SIZE);
+        network.set_virtual_channels(self.vcs);
+        network.set_deadlock_check(self.deadlock_check);
+        network.set_sample_interval(self.sample_interval);
+        if self.hop_routing() {
+            network.set_routing(Some(self.routing()));
+        }
+        self.network = network;
 
-                    let cur_node = channel_map.entry((x, y)).or_default();
-                    cur_node.tx_up = Some(tx_up);
-                    let down_node = channel_map.entry((x, y - 1)).or_default();
-                    down_node.rx_down = Some(rx_up);
-                }
-                if x > 0 {
-                    let (tx_left, rx_left) = mpsc::channel::<Packet>(LINK_BUFFER_SIZE);
+        Ok(event_rx)
+    }
 
-                    let cur_node = channel_map.entry((x, y)).or_default();
-                    cur_node.tx_left = Some(tx_left);
-                    let right_node = channel_map.entry((x - 1, y)).or_default();
-                    right_node.rx_right = Some(rx_left);
-                }
-                if x < width {
-                    let (tx_right, rx_right) = mpsc::channel::<Packet>(LINK_BUFFER_SIZE);
+    // Builds the grid a config describes, which is validated first
+    pub fn from_config(config: &Config) -> Result<(Self, UnboundedReceiver<Event>), ConfigError> {
+        config.validate()?;
 
-                    let cur_node = channel_map.entry((x, y)).or_default();
-                    cur_node.tx_right = Some(tx_right);
-                    let left_node = channel_map.entry((x + 1, y)).or_default();
-                    left_node.rx_left = Some(rx_right);
-                }
+        let topology = config.topology;
+        let defaults = config.nodes.params();
+        let mut builder = GridBuilder::new(topology.width, topology.height)
+            .depth(topology.depth)
+            .topology(topology.kind)
+            .link_params(config.links)
+            .vertical_link_params(config.vertical_links)
+            .node_params(defaults)
+            .router_pipeline(config.router.pipeline)
+            .virtual_channels(config.router.virtual_channels())
+            .deadlock_check(config.router.deadlock_check);
+        for node in &config.nodes.overrides {
+            builder = builder.node_params_at(node.at, node.params(defaults));
+        }
+        builder = match config.routing {
+            None => builder,
+            Some(RoutingKind::NegativeFirst) => builder.routing(NegativeFirst),
+            Some(RoutingKind::WestFirst) => builder.routing(WestFirst),
+            Some(RoutingKind::NorthLast) => builder.routing(NorthLast),
+            Some(RoutingKind::OddEven) => builder.routing(OddEven),
+        };
 
-                grid_row.push(cur_node);
-            }
+        builder.build()
+    }
+
+    // Starts a grid of width x height nodes set up through a GridBuilder instead of the setters
+    pub fn builder(width: Dim, height: Dim) -> GridBuilder {
+        GridBuilder::new(width, height)
+    }
+
+    // Shape the next init_grid would build, without building it
+    pub(crate) fn set_dimensions(&mut self, width: Dim, height: Dim, depth: Dim) {
+        self.width = width;
+        self.height = height;
+        self.depth = depth;
+    }
+
+    // Width, height and depth the grid was built with
+    pub fn dimensions(&self) -> (Dim, Dim, Dim) {
+        (self.width, self.height, self.depth)
+    }
+
+    pub fn access_node(&self, pos: impl Into<Coord>) -> Result<&MeshNode, GridAccessError> {
+        let node = self.node_id(pos.into())?;
+        Ok(self.network.node(node))
+    }
+
+    // Position of the node on the other end of the link leaving pos in dir, edge nodes of a mesh
+    // have no link towards the outside
+    // - A torus axis a single node long doesn't wrap, the node would be linked to itself
+    pub(crate) fn neighbour(&self, Coord { x, y, z }: Coord, dir: Direction) -> Option<Coord> {
+        let (width, height) = (self.width, self.height);
+        let torus = self.kind == TopologyKind::Torus;
+        let (wrap_x, wrap_y) = (torus && width > 1, torus && height > 1);
+
+        let (x, y, z) = match dir {
+            Direction::Up if wrap_y => (x, y.checked_sub(1).unwrap_or(height - 1), z),
+            Direction::Down if wrap_y => (x, (y + 1) % height, z),
+            Direction::Left if wrap_x => (x.checked_sub(1).unwrap_or(width - 1), y, z),
+            Direction::Right if wrap_x => ((x + 1) % width, y, z),
+            Direction::Up => (x, y.checked_sub(1)?, z),
+            Direction::Down => (x, (y + 1 < height).then_some(y + 1)?, z),
+            Direction::Left => (x.checked_sub(1)?, y, z),
+            Direction::Right => ((x + 1 < width).then_some(x + 1)?, y, z),
+            Direction::Above => (x, y, (z + 1 < self.depth).then_some(z + 1)?),
+            Direction::Below => (x, y, z.checked_sub(1)?),
+            Direction::Port(_) | Direction::Init => return None,
+        };
+
+        Some(Coord { x, y, z })
+    }
 
-            temp_nodes.push(grid_row);
+    fn link_params(&self, dir: Direction) -> LinkParams {
+        if dir.is_vertical() {
+            self.vertical_link
+        } else {
+            self.planar_link
         }
+    }
 
-        // Create the Arc MeshNode holder for thread safe access + optimized space usage
-        let inner_nodes_arc: Vec<Arc<[MeshNode]>> = temp_nodes
-            .into_iter()
-            .map(|node_row| Arc::from(node_row.into_boxed_slice()))
-            .collect();
-        self.nodes = Arc::from(inner_nodes_arc.into_boxed_slice());
-
-        for y in 0..height {
-            for x in 0..width {
-                let mut tx_up = None;
-                let mut tx_down = None;
-                let mut tx_left = None;
-                let mut tx_right = None;
-                let mut rx_up = None;
-                let mut rx_down = None;
-                let mut rx_left = None;
-                let mut rx_right = None;
-
-                if let Some(rx_conns) = channel_map.get_mut(&(x, y)) {
-                    tx_up = rx_conns.tx_up.clone();
-                    tx_down = rx_conns.tx_down.clone();
-                    tx_left = rx_conns.tx_left.clone();
-                    tx_right = rx_conns.tx_right.clone();
-                    rx_up = std::mem::take(&mut rx_conns.rx_up);
-                    rx_down = std::mem::take(&mut rx_conns.rx_down);
-                    rx_left = std::mem::take(&mut rx_conns.rx_left);
-                    rx_right = std::mem::take(&mut rx_conns.rx_right);
-                }
+    // Picks the topology built by the next init_grid
+    pub fn set_topology(&mut self, kind: TopologyKind) {
+        self.kind = kind;
+    }
 
-                let (inner_tx_up, inner_rx_up) = mpsc::channel(INNER_BUFFER_SIZE);
-                let (inner_tx_down, inner_rx_down) = mpsc::channel(INNER_BUFFER_SIZE);
-                let (inner_tx_left, inner_rx_left) = mpsc::channel(INNER_BUFFER_SIZE);
-                let (inner_tx_right, inner_rx_right) = mpsc::channel(INNER_BUFFER_SIZE);
-
-                if let Some(inner_rx_local) = rx_local_map.remove(&(x, y)) {
-                    let tx_event_receive = event_tx.clone();
-                    let tx_event_send = event_tx.clone();
-                    let cur_node = self.access_node((x, y))?;
-                    let (tx_rate, rx_rate) = (cur_node.tx_rate, cur_node.rx_rate);
-
-                    tokio::spawn(async move {
-                        receive_packets(
-                            rx_up,
-                            rx_down,
-                            rx_left,
-                            rx_right,
-                            &inner_tx_up,
-                            &inner_tx_down,
-                            &inner_tx_left,
-                            &inner_tx_right,
-                            &tx_event_receive,
-                            tx_rate,
-                        )
-                        .await
-                    });
+    pub fn topology(&self) -> TopologyKind {
+        self.kind
+    }
+
+    // Switches every node built by the next init_grid over to the cycle accurate router model
+    pub fn set_router_pipeline(&mut self, pipeline: Option<RouterPipeline>) {
+        self.pipeline = pipeline;
+    }
+
+    // Virtual channels on every link built by the next init_grid
+    pub fn set_virtual_channels(&mut self, vcs: VirtualChannels) {
+        self.vcs = vcs;
+    }
+
+    // Cycles between deadlock checks while the next init_grid runs, a stalled network is always
+    // checked
+    pub fn set_deadlock_check(&mut self, interval: Option<Cycle>) {
+        self.deadlock_check = interval;
+    }
+
+    pub fn detect_deadlock(&self) -> Option<Deadlock> {
+        self.network.detect_deadlock()
+    }
+
+    // Samples the occupancy of every buffer every interval cycles, from the current cycle on and
+    // in every grid built by the next init_grid
+    // - Some(0) samples every cycle like Some(1), GridBuilder and config files reject it instead
+    pub fn set_sample_interval(&mut self, interval: Option<Cycle>) {
+        self.sample_interval = interval;
+        self.network.set_sample_interval(interval);
+    }
+
+    // Counters of every link in the grid
+    pub fn link_usage(&self) -> Vec<LinkUsage> {
+        self.network.link_usage()
+    }
+
+    // Buffer occupancy and link totals sampled so far
+    pub fn time_series(&self) -> &TimeSeries {
+        self.network.time_series()
+    }
+
+    // Nodes and links as Graphviz DOT, see export::topology
+    pub fn write_dot(&self, out: impl io::Write) -> io::Result<()> {
+        topology::write_dot(&self.network, out)
+    }
+
+    // Same picture drawn as SVG without Graphviz
+    pub fn write_svg(&self, out: impl io::Write) -> io::Result<()> {
+        topology::write_svg(&self.network, out)
+    }
+
+    // Rates and buffer depths of every node built by the next init_grid
+    pub fn set_node_params(&mut self, params: NodeParams) {
+        self.node_params = params;
+    }
+
+    // Same for the node at pos alone, wins over set_node_params and is ignored if the grid has no
+    // node there
+    pub fn set_node_params_at(&mut self, pos: impl Into<Coord>, params: NodeParams) {
+        self.node_overrides.insert(pos.into(), params);
+    }
+
+    // Parameters of the links inside a layer
+    pub fn set_link_params(&mut self, params: LinkParams) {
+        self.planar_link = params;
+    }
+
+    // Parameters of the links between layers
+    pub fn set_vertical_link_params(&mut self, params: LinkParams) {
+        self.vertical_link = params;
+    }
+
+    // Same for the link leaving pos towards dir alone, wins over the two above and is ignored if
+    // the grid has no such link
+    pub fn set_link_params_at(
+        &mut self,
+        pos: impl Into<Coord>,
+        dir: Direction,
+        params: LinkParams,
+    ) {
+        self.link_overrides.insert((pos.into(), dir), params);
+    }
+
+    // Sends the events of the current network to event_tx, the receiver init_grid returned gets
+    // nothing from then on
+    pub fn set_event_sink(&mut self, event_tx: UnboundedSender<Event>) {
+        self.network.set_event_sink(event_tx);
+    }
+
+    // Routing algorithm the nodes of the next init_grid run on every hop
+    // - Only 2D meshes route hop by hop, a torus or stacked mesh keeps its shortest path or
+    // dimension ordered source routes since the turn models don't cover wrap or vertical links
+    pub fn set_routing(&mut self, routing: impl RoutingAlgorithm + 'static) {
+        self.routing = Some(Arc::new(routing));
+    }
+
+    pub(crate) fn set_shared_routing(&mut self, routing: Arc<dyn RoutingAlgorithm>) {
+        self.routing = Some(routing);
+    }
+
+    pub fn routing(&self) -> Arc<dyn RoutingAlgorithm> {
+        self.routing
+            .clone()
+            .unwrap_or_else(|| Arc::new(NegativeFirst))
+    }
+
+    fn hop_routing(&self) -> bool {
+        self.kind == TopologyKind::Mesh && self.depth <= 1
+    }
+
+    pub fn cycle(&self) -> Cycle {
+        self.network.cycle()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.network.is_idle()
+    }
+
+    // Takes a packet and queues it for injection at its source node on the current cycle
+    // - On a 2D mesh every node picks the next hop as the packet passes, otherwise the path is
+    // calculated up front and the nodes carry it from there
+    // - Returns the id the packet was given
+    pub fn send_packet_grid(&mut self, packet: Packet) -> Result<usize, NodeCommError> {
+        self.send_packet_at(packet, self.cycle())
+    }
+
+    // Same as send_packet_grid but the packet is only injected on cycle, which can't be in the past
+    pub fn send_packet_at(&mut self, packet: Packet, cycle: Cycle) -> Result<usize, NodeCommError> {
+        let src = self.node_id(packet.header.cur_pos)?;
+        let dest = self.node_id(packet.header.dest_pos)?;
+        if self.hop_routing() {
+            return Ok(self.network.inject_at(src, packet, Vec::new(), cycle));
+        }
+
+        let path = self.route(src, dest).ok_or(NodeCommError::NoRoute {
+            src: packet.header.cur_pos,
+            dest: packet.header.dest_pos,
+        })?;
 
-                    tokio::spawn(async move {
-                        send_packet(
-                            tx_up.as_ref(),
-                            tx_down.as_ref(),
-                            tx_left.as_ref(),
-                            tx_right.as_ref(),
-                            &tx_event_send,
-                            inner_rx_up,
-                            inner_rx_down,
-                            inner_rx_left,
-                            inner_rx_right,
-                            inner_rx_local,
-                            rx_rate,
-                        )
-                        .await
+        Ok(self.network.inject_at(src, packet, path, cycle))
+    }
+
+    // Processes events until the network has nothing left to do and returns the cycle it went
+    // idle on
+    pub fn run(&mut self) -> Result<Cycle, NodeCommError> {
+        self.network.run()
+    }
+
+    // Runs a single cycle, see Network::step
+    pub fn step(&mut self) -> Result<Option<Cycle>, NodeCommError> {
+        self.network.step()
+    }
+
+    // Stops before cycle end, anything scheduled from then on is left for the next run
+    pub fn run_until(&mut self, end: Cycle) -> Result<Cycle, NodeCommError> {
+        self.network.run_until(end)
+    }
+}
+
+impl Topology for Grid {
+    fn node_count(&self) -> usize {
+        self.width as usize * self.height as usize * self.depth as usize
+    }
+
+    fn port_count(&self, _node: NodeId) -> usize {
+        PORT_COUNT
+    }
+
+    // Layer major, then row major
+    fn coord(&self, node: NodeId) -> Coord {
+        let (width, height) = (self.width as usize, self.height as usize);
+
+        Coord::new(
+            (node % width) as Dim,
+            (node / width % height) as Dim,
+            (node / (width * height)) as Dim,
+        )
+    }
+
+    fn node_id(&self, Coord { x, y, z }: Coord) -> Result<NodeId, GridAccessError> {
+        if x >= self.width {
+            return Err(GridAccessError::InvalidWidth(x));
+        }
+        if y >= self.height {
+            return Err(GridAccessError::InvalidHeight(y));
+        }
+        if z >= self.depth {
+            return Err(GridAccessError::InvalidDepth(z));
+        }
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        Ok((z as usize * height + y as usize) * width + x as usize)
+    }
+
+    fn port_dir(&self, _node: NodeId, port: PortId) -> Direction {
+        Direction::PORTS[port]
+    }
+
+    fn links(&self) -> Vec<Link> {
+        let mut links = Vec::new();
+
+        for src in 0..self.node_count() {
+            for dir in Direction::PORTS {
+                let pos = self.coord(src);
+                if let Some(next) = self.neighbour(pos, dir)
+                    && let Ok(dest) = self.node_id(next)
+                {
+                    // The wrap links of a torus are the datelines of its rings
+                    let dateline = match dir {
+                        Direction::Up => next.y > pos.y,
+                        Direction::Down => next.y < pos.y,
+                        Direction::Left => next.x > pos.x,
+                        Direction::Right => next.x < pos.x,
+                        _ => false,
+                    };
+
+                    links.push(Link {
+                        src,
+                        src_port: dir.index(),
+                        dest,
+                        dest_port: dir.opposite().index(),
+                        params: self
+                            .link_overrides
+                            .get(&(pos, dir))
+                            .copied()
+                            .unwrap_or(self.link_params(dir)),
+                        dateline,
                     });
-                } else {
-                    panic!("Failed to retrieve inner rx for node ({x}, {y})");
                 }
             }
         }
 
-        Ok(event_rx)
+        links
     }
 
-    pub fn access_node(&self, (x, y): (u8, u8)) -> Result<&MeshNode, GridAccessError> {
-        let node_row = self
-            .nodes
-            .get(y as usize)
-            .ok_or_else(|| GridAccessError::InvalidHeight(x))?;
-        let node = node_row
-            .get(x as usize)
-            .ok_or_else(|| GridAccessError::InvalidWidth(y))?;
-
-        Ok(&node)
-    }
+    fn route(&self, src: NodeId, dest: NodeId) -> Option<Vec<PortId>> {
+        let (src, dest) = (self.coord(src), self.coord(dest));
+        let wrap = self.kind == TopologyKind::Torus;
 
-    // Takes a packet and sends it from a src node to a destination node
-    // Calculates the first direction and enquues in that mpsc tx, nodes carry from there
-    pub async fn send_packet_grid(
-        node: &MeshNode,
-        mut packet: Packet,
-    ) -> Result<(), NodeCommError> {
-        packet.header.path = calc_path(packet.header.cur_pos, packet.header.dest_pos);
-        node.tx_local.send(packet).await?;
+        let path = if self.depth > 1 {
+            calc_path_3d(src, dest, (self.width, self.height), wrap)
+        } else if wrap {
+            calc_torus_path(src, dest, (self.width, self.height))
+        } else {
+            preferred_path(self.routing().as_ref(), src, dest)?
+        };
 
-        Ok(())
+        Some(path.into_iter().map(Direction::index).collect())
     }
 }
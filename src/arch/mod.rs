@@ -1,2 +1,7 @@
+pub mod builder;
+pub mod coord;
 pub mod grid;
 pub mod node;
+pub mod router;
+pub mod routing;
+pub mod topology;
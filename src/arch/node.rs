@@ -1,30 +1,193 @@
-use tokio::sync::mpsc::Sender;
+use std::collections::VecDeque;
 
-use crate::comm::packet::Packet;
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::{INNER_BUFFER_SIZE, LINK_BUFFER_SIZE, NODE_RATE};
+use crate::arch::topology::{Link, NodeId, PortId};
+use crate::comm::packet::{Flit, Packet};
+use crate::comm::transfer::Direction;
+use crate::sim::engine::Cycle;
+
+// Rates and buffer depths of a single node
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct NodeParams {
+    pub tx_rate: u64,
+    pub rx_rate: u64,
+    // Per virtual channel, in flits
+    pub link_buffer: usize,
+    pub inner_buffer: usize,
+}
+
+impl Default for NodeParams {
+    fn default() -> Self {
+        Self {
+            tx_rate: NODE_RATE,
+            rx_rate: NODE_RATE,
+            link_buffer: LINK_BUFFER_SIZE,
+            inner_buffer: INNER_BUFFER_SIZE,
+        }
+    }
+}
 
 // Need some way to handle data transfer
+// - Rates are in flits per cycle, the receive stage handles up to rx_rate flits and the send stage
+// up to tx_rate flits each time the node is serviced
+// - Every per port vector is indexed by PortId, the buffers then by virtual channel
 pub struct MeshNode {
-    pub x: u8,
-    pub y: u8,
+    pub x: Dim,
+    pub y: Dim,
+    pub z: Dim,
     pub tx_rate: u64,
     pub rx_rate: u64,
-    pub tx_local: Sender<Packet>,
+    // Depth of every link and inner buffer per virtual channel
+    pub(crate) link_buffer: usize,
+    pub(crate) inner_buffer: usize,
+    // Name of each port in events
+    pub(crate) ports: Vec<Direction>,
+    pub(crate) out_links: Vec<Option<Link>>,
+    // Free slots the node knows of in the link buffers at the far end of each outgoing link, one
+    // is spent on every flit sent and they come back as credits once the flit moves on
+    pub(crate) credits: Vec<Vec<usize>>,
+    // Node and port on the sending end of each incoming link
+    pub(crate) in_links: Vec<Option<(NodeId, PortId)>>,
+    // Link buffers indexed by the port the packet came in on
+    pub(crate) link_buffers: Vec<Vec<VecDeque<Flit>>>,
+    pub(crate) inner_buffers: Vec<Vec<VecDeque<Flit>>>,
+    vc_count: usize,
+    // Flits of the packets injected at this node that haven't entered the network yet
+    pub(crate) local_queue: VecDeque<Flit>,
+    // Output port and virtual channel each source (inner buffer or local queue, see send_packet)
+    // is streaming the packet at its head to, set by the head flit and cleared by the tail
+    pub(crate) routes: Vec<Option<(PortId, usize)>>,
+    // Cycle, output and channel since which the flit at the head of each source has been waiting
+    // for a credit
+    pub(crate) stalled: Vec<Option<(Cycle, PortId, usize)>>,
+    // Output virtual channels currently held by a packet
+    pub(crate) out_vc_held: Vec<Vec<bool>>,
+    // Packet whose flits are being taken off each input because it has reached its destination
+    pub(crate) ejecting: Vec<Vec<Option<Packet>>>,
+    // Round robin pointers so a busy port can't starve the others
+    pub(crate) recv_rr: usize,
+    pub(crate) send_rr: usize,
+    pub(crate) last_service: Option<Cycle>,
 }
 
 impl MeshNode {
-    pub fn init_channeless(
-        x: u8,
-        y: u8,
-        tx_rate: u64,
-        rx_rate: u64,
-        tx_local: Sender<Packet>,
-    ) -> Self {
+    pub fn new(pos: Coord, ports: Vec<Direction>, tx_rate: u64, rx_rate: u64) -> Self {
+        let port_count = ports.len();
+        let buffers = || (0..port_count).map(|_| vec![VecDeque::new()]).collect();
+
         Self {
-            x,
-            y,
+            x: pos.x,
+            y: pos.y,
+            z: pos.z,
             tx_rate,
             rx_rate,
-            tx_local,
+            link_buffer: LINK_BUFFER_SIZE,
+            inner_buffer: INNER_BUFFER_SIZE,
+            ports,
+            out_links: vec![None; port_count],
+            credits: vec![vec![0]; port_count],
+            in_links: vec![None; port_count],
+            link_buffers: buffers(),
+            inner_buffers: buffers(),
+            vc_count: 1,
+            local_queue: VecDeque::new(),
+            routes: vec![None; port_count + 1],
+            stalled: vec![None; port_count + 1],
+            out_vc_held: vec![vec![false]; port_count],
+            ejecting: (0..port_count).map(|_| vec![None]).collect(),
+            recv_rr: 0,
+            send_rr: 0,
+            last_service: None,
         }
     }
+
+    pub fn pos(&self) -> Coord {
+        Coord::new(self.x, self.y, self.z)
+    }
+
+    pub fn port_count(&self) -> usize {
+        self.ports.len()
+    }
+
+    pub fn vc_count(&self) -> usize {
+        self.vc_count
+    }
+
+    pub fn params(&self) -> NodeParams {
+        NodeParams {
+            tx_rate: self.tx_rate,
+            rx_rate: self.rx_rate,
+            link_buffer: self.link_buffer,
+            inner_buffer: self.inner_buffer,
+        }
+    }
+
+    // Only while the node is empty, the nodes sending to it have to be given the new link buffer
+    // depth as credits, see Network::set_node_params
+    pub fn set_params(&mut self, params: NodeParams) {
+        assert!(
+            params.link_buffer > 0 && params.inner_buffer > 0,
+            "A buffer needs room for at least one flit"
+        );
+        assert!(!self.has_work(), "Can't resize the buffers of a busy node");
+
+        self.tx_rate = params.tx_rate;
+        self.rx_rate = params.rx_rate;
+        self.link_buffer = params.link_buffer;
+        self.inner_buffer = params.inner_buffer;
+    }
+
+    // Gives every port vc_count independent link and inner buffers, only while the node is empty
+    // - Credits depend on the buffers at the far end of each link, the network hands them back out
+    pub fn set_vc_count(&mut self, vc_count: usize) {
+        assert!(vc_count > 0, "A link needs at least one virtual channel");
+        assert!(
+            !self.has_work(),
+            "Can't change virtual channels of a busy node"
+        );
+
+        let port_count = self.port_count();
+        let buffers = || {
+            (0..port_count)
+                .map(|_| (0..vc_count).map(|_| VecDeque::new()).collect())
+                .collect()
+        };
+        self.link_buffers = buffers();
+        self.inner_buffers = buffers();
+        self.vc_count = vc_count;
+        self.credits = vec![vec![0; vc_count]; port_count];
+        self.routes = vec![None; port_count * vc_count + 1];
+        self.stalled = vec![None; port_count * vc_count + 1];
+        self.out_vc_held = vec![vec![false; vc_count]; port_count];
+        self.ejecting = (0..port_count)
+            .map(|_| (0..vc_count).map(|_| None).collect())
+            .collect();
+    }
+
+    // Hooks up the outgoing link on port, starting with all slots of the link buffer at the far
+    // end free
+    pub fn connect(&mut self, port: PortId, link: Link, slots: usize) {
+        self.out_links[port] = Some(link);
+        self.refill(port, slots);
+    }
+
+    // Credits of every virtual channel on port back to slots
+    pub(crate) fn refill(&mut self, port: PortId, slots: usize) {
+        self.credits[port] = vec![slots; self.vc_count];
+    }
+
+    pub fn has_work(&self) -> bool {
+        !self.local_queue.is_empty()
+            || self
+                .link_buffers
+                .iter()
+                .flatten()
+                .any(|buffer| !buffer.is_empty())
+            || self
+                .inner_buffers
+                .iter()
+                .flatten()
+                .any(|buffer| !buffer.is_empty())
+    }
 }
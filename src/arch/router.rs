@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::comm::packet::MetaData;
+use crate::sim::engine::Cycle;
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub enum PipelineStage {
+    BufferWrite,
+    RouteCompute,
+    VcAllocation,
+    SwitchAllocation,
+    SwitchTraversal,
+    LinkTraversal,
+}
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct StageTiming {
+    pub stage: PipelineStage,
+    pub start: Cycle,
+    pub end: Cycle,
+}
+
+// Latency in cycles of every stage of the classic router pipeline
+// - Link traversal takes the latency of the link the packet leaves on, see LinkParams
+// - A packet is written into the input buffer and has its output computed, then it waits at the
+// head of its buffer until it is granted a downstream slot (VC allocation) and the output port
+// (switch allocation) on the same cycle, after which it crosses the switch and the link
+// - Waiting for the grant counts as VC allocation time in the reported timings
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouterPipeline {
+    pub buffer_write: Cycle,
+    pub route_compute: Cycle,
+    pub vc_allocation: Cycle,
+    pub switch_allocation: Cycle,
+    pub switch_traversal: Cycle,
+}
+
+impl Default for RouterPipeline {
+    fn default() -> Self {
+        Self {
+            buffer_write: 1,
+            route_compute: 1,
+            vc_allocation: 1,
+            switch_allocation: 1,
+            switch_traversal: 1,
+        }
+    }
+}
+
+impl RouterPipeline {
+    // Cycles between a packet entering the router and it being able to compete for an output
+    pub fn ready_delay(&self) -> Cycle {
+        self.buffer_write + self.route_compute
+    }
+
+    // Cycles between a packet being granted an output and it landing in the next router
+    pub fn departure_delay(&self, link_latency: Cycle) -> Cycle {
+        self.vc_allocation + self.switch_allocation + self.switch_traversal + link_latency
+    }
+
+    // Body and tail flits reuse the route and channel of their head and only go through the switch
+    pub fn body_delay(&self, link_latency: Cycle) -> Cycle {
+        self.switch_allocation + self.switch_traversal + link_latency
+    }
+
+    // Per stage timings of a packet that entered the router on hop_start and was granted its
+    // output on granted
+    pub fn timings(
+        &self,
+        hop_start: Cycle,
+        granted: Cycle,
+        link_latency: Cycle,
+    ) -> Vec<StageTiming> {
+        let stages = [
+            (PipelineStage::BufferWrite, self.buffer_write),
+            (PipelineStage::RouteCompute, self.route_compute),
+            (PipelineStage::VcAllocation, self.vc_allocation),
+            (PipelineStage::SwitchAllocation, self.switch_allocation),
+            (PipelineStage::SwitchTraversal, self.switch_traversal),
+            (PipelineStage::LinkTraversal, link_latency),
+        ];
+
+        let mut start = hop_start;
+        stages
+            .into_iter()
+            .map(|(stage, latency)| {
+                // Allocation starts as soon as the route is known but only finishes once granted
+                let end = match stage {
+                    PipelineStage::VcAllocation => granted + latency,
+                    _ => start + latency,
+                };
+                let timing = StageTiming { stage, start, end };
+                start = end;
+                timing
+            })
+            .collect()
+    }
+}
+
+// How a packet picks the virtual channel it takes on the next link
+#[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum VcSelection {
+    // Any virtual channel with room
+    #[default]
+    Any,
+    // The channels are split into one group per message class and a packet only uses the group of
+    // its class, so one class backing up can't block another
+    Class {
+        classes: usize,
+    },
+    // The channels are split into one group per routing phase, a packet moves up a phase each time
+    // it crosses a dateline link and starts over when it turns into another dimension
+    // - Two phases break the cycle of every torus ring
+    Phase {
+        phases: usize,
+    },
+}
+
+// Number of virtual channels on every link, each with its own buffer at both ends, and how they
+// are handed out
+// - The channels of a link share its bandwidth, the switch arbitrates between them round robin
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct VirtualChannels {
+    pub count: usize,
+    pub selection: VcSelection,
+}
+
+impl Default for VirtualChannels {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            selection: VcSelection::Any,
+        }
+    }
+}
+
+impl VirtualChannels {
+    // Channels a packet may take on the next link, phase is the one it will be in on that link
+    // - A class or phase past the last group shares the last group
+    pub fn allowed(&self, header: &MetaData, phase: usize) -> Range<usize> {
+        let (group, groups) = match self.selection {
+            VcSelection::Any => (0, 1),
+            VcSelection::Class { classes } => (header.class, classes),
+            VcSelection::Phase { phases } => (phase, phases),
+        };
+        let groups = groups.clamp(1, self.count);
+        let group = group.min(groups - 1);
+
+        group * self.count / groups..(group + 1) * self.count / groups
+    }
+
+    // The allowed channel with the most free slots downstream, ties go to the lowest channel
+    // - None if every allowed channel is full
+    pub fn allocate(&self, allowed: Range<usize>, free: &[usize]) -> Option<usize> {
+        allowed
+            .rev()
+            .filter(|vc| free[*vc] > 0)
+            .max_by_key(|vc| free[*vc])
+    }
+}
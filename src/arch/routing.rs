@@ -0,0 +1,173 @@
+use crate::arch::coord::Coord;
+use crate::comm::transfer::Direction;
+
+// Routing evaluated hop by hop at every node of a 2D mesh
+// - Returns the directions a packet at cur may take next, most preferred first, the node then
+// picks the one whose downstream buffer has the most free slots
+// - Up is north and Down is south, so y grows towards the south
+// - Anywhere but dest there has to be at least one candidate and each has to stay inside the mesh,
+// a packet without one is stuck where it is and Grid::route has no path for it
+pub trait RoutingAlgorithm: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn candidates(&self, src: Coord, cur: Coord, dest: Coord) -> Vec<Direction>;
+}
+
+// Path a packet takes when every buffer is equally free, the most preferred direction at every hop
+// - None if routing runs out of candidates or steps off the edge of the coordinates on the way
+pub fn preferred_path(
+    routing: &dyn RoutingAlgorithm,
+    src: Coord,
+    dest: Coord,
+) -> Option<Vec<Direction>> {
+    let mut path = Vec::new();
+    let mut cur = src;
+
+    while cur != dest {
+        let dir = *routing.candidates(src, cur, dest).first()?;
+        match dir {
+            Direction::Up => cur.y = cur.y.checked_sub(1)?,
+            Direction::Down => cur.y = cur.y.checked_add(1)?,
+            Direction::Left => cur.x = cur.x.checked_sub(1)?,
+            Direction::Right => cur.x = cur.x.checked_add(1)?,
+            _ => return None,
+        }
+        path.push(dir);
+    }
+
+    Some(path)
+}
+
+// Offsets towards the destination, positive east and positive north
+fn offsets(cur: Coord, dest: Coord) -> (i64, i64) {
+    let east = dest.x as i64 - cur.x as i64;
+    let north = cur.y as i64 - dest.y as i64;
+
+    (east, north)
+}
+
+fn east_west(east: i64) -> Option<Direction> {
+    match east.signum() {
+        1 => Some(Direction::Right),
+        -1 => Some(Direction::Left),
+        _ => None,
+    }
+}
+
+fn north_south(north: i64) -> Option<Direction> {
+    match north.signum() {
+        1 => Some(Direction::Up),
+        -1 => Some(Direction::Down),
+        _ => None,
+    }
+}
+
+// All west hops are taken first and without any choice, the rest is fully adaptive
+#[derive(Copy, Clone, Default, Debug)]
+pub struct WestFirst;
+
+impl RoutingAlgorithm for WestFirst {
+    fn name(&self) -> &'static str {
+        "west-first"
+    }
+
+    fn candidates(&self, _src: Coord, cur: Coord, dest: Coord) -> Vec<Direction> {
+        let (east, north) = offsets(cur, dest);
+        if east < 0 {
+            return vec![Direction::Left];
+        }
+
+        east_west(east)
+            .into_iter()
+            .chain(north_south(north))
+            .collect()
+    }
+}
+
+// Fully adaptive until only north hops are left, those are taken last
+#[derive(Copy, Clone, Default, Debug)]
+pub struct NorthLast;
+
+impl RoutingAlgorithm for NorthLast {
+    fn name(&self) -> &'static str {
+        "north-last"
+    }
+
+    fn candidates(&self, _src: Coord, cur: Coord, dest: Coord) -> Vec<Direction> {
+        let (east, north) = offsets(cur, dest);
+        let south = (north < 0).then_some(Direction::Down);
+        let candidates: Vec<Direction> = east_west(east).into_iter().chain(south).collect();
+
+        if candidates.is_empty() {
+            north_south(north).into_iter().collect()
+        } else {
+            candidates
+        }
+    }
+}
+
+// West and south are the negative directions and are all taken before any positive one, each
+// phase is adaptive between its two directions
+// - With every buffer equally free this takes the same path the old calc_path source route did
+#[derive(Copy, Clone, Default, Debug)]
+pub struct NegativeFirst;
+
+impl RoutingAlgorithm for NegativeFirst {
+    fn name(&self) -> &'static str {
+        "negative-first"
+    }
+
+    fn candidates(&self, _src: Coord, cur: Coord, dest: Coord) -> Vec<Direction> {
+        let (east, north) = offsets(cur, dest);
+        let west = (east < 0).then_some(Direction::Left);
+        let south = (north < 0).then_some(Direction::Down);
+        let negative: Vec<Direction> = west.into_iter().chain(south).collect();
+
+        if negative.is_empty() {
+            east_west(east)
+                .into_iter()
+                .chain(north_south(north))
+                .collect()
+        } else {
+            negative
+        }
+    }
+}
+
+// Chiu's odd-even turn model, east to north/south turns are forbidden in even columns and
+// north/south to west turns in odd columns
+#[derive(Copy, Clone, Default, Debug)]
+pub struct OddEven;
+
+impl RoutingAlgorithm for OddEven {
+    fn name(&self) -> &'static str {
+        "odd-even"
+    }
+
+    fn candidates(&self, src: Coord, cur: Coord, dest: Coord) -> Vec<Direction> {
+        let (east, north) = offsets(cur, dest);
+        let mut candidates = Vec::new();
+
+        if east == 0 {
+            candidates.extend(north_south(north));
+        } else if east > 0 {
+            if north == 0 {
+                candidates.push(Direction::Right);
+            } else {
+                if !cur.x.is_multiple_of(2) || cur.x == src.x {
+                    candidates.extend(north_south(north));
+                }
+                if !dest.x.is_multiple_of(2) || east != 1 {
+                    candidates.push(Direction::Right);
+                }
+            }
+        } else {
+            candidates.push(Direction::Left);
+            if cur.x.is_multiple_of(2) {
+                candidates.extend(north_south(north));
+            }
+        }
+
+        candidates
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::GridAccessError;
+use crate::comm::transfer::Direction;
+use crate::sim::engine::Cycle;
+
+pub type NodeId = usize;
+pub type PortId = usize;
+
+// Cycles a packet spends on a link between leaving one node and landing in the next
+pub const LINK_LATENCY: Cycle = 1;
+// Flits a link can take on per cycle
+pub const LINK_BANDWIDTH: usize = 1;
+// Cycles a credit spends travelling back from the receiving node to the sending one
+pub const CREDIT_LATENCY: Cycle = 1;
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkParams {
+    // Cycles spent crossing the link
+    pub latency: Cycle,
+    // Flits the link can take on per cycle
+    pub bandwidth: usize,
+    // Cycles spent by a credit on the way back
+    pub credit_latency: Cycle,
+}
+
+impl Default for LinkParams {
+    fn default() -> Self {
+        Self {
+            latency: LINK_LATENCY,
+            bandwidth: LINK_BANDWIDTH,
+            credit_latency: CREDIT_LATENCY,
+        }
+    }
+}
+
+// One directional link, packets leave src through src_port and come in at dest through dest_port
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Link {
+    pub src: NodeId,
+    pub src_port: PortId,
+    pub dest: NodeId,
+    pub dest_port: PortId,
+    pub params: LinkParams,
+    // Packets crossing this link move up a routing phase, see VcSelection::Phase
+    pub dateline: bool,
+}
+
+// Describes how the nodes of a network are wired together
+// - Nodes are numbered 0..node_count and every node has its own set of ports, a port can have an
+// outgoing link, an incoming link, both or neither
+// - Coordinates are how nodes are addressed from the outside and reported in events
+pub trait Topology {
+    fn node_count(&self) -> usize;
+
+    fn port_count(&self, node: NodeId) -> usize;
+
+    fn coord(&self, node: NodeId) -> Coord;
+
+    fn node_id(&self, pos: Coord) -> Result<NodeId, GridAccessError>;
+
+    // Name of a port in events
+    fn port_dir(&self, node: NodeId, port: PortId) -> Direction;
+
+    fn links(&self) -> Vec<Link>;
+
+    // Output ports a packet leaves through on its way from src to dest, None if dest can't be
+    // reached from src
+    fn route(&self, src: NodeId, dest: NodeId) -> Option<Vec<PortId>>;
+}
+
+// Arbitrary topology described by an explicit list of links
+// - Works for rings, trees, butterflies, dragonflies or any irregular layout
+// - Routes along a shortest path in hops, ties go to the link that was added first
+#[derive(Default, Clone, Debug)]
+pub struct Graph {
+    coords: Vec<Coord>,
+    ports: Vec<usize>,
+    links: Vec<Link>,
+    lookup: HashMap<Coord, NodeId>,
+}
+
+impl Graph {
+    // Node i sits at (i, 0, 0) until it is given another position
+    pub fn new(node_count: Dim) -> Self {
+        let mut graph = Self::default();
+        for x in 0..node_count {
+            graph.add_node(Coord::new(x, 0, 0));
+        }
+
+        graph
+    }
+
+    // Builds a graph where adjacency[i] lists the nodes i has a link to
+    // - Each entry gets its own port, a link that has a matching link back shares that port on
+    // both ends
+    // - A node listed k times is linked k times, the k-th of those links comes in on the port of
+    // the k-th link back so parallel links never share a port
+    // - Fails on a neighbour that isn't one of the nodes or on more nodes than coordinates
+    pub fn from_adjacency(
+        adjacency: &[Vec<NodeId>],
+        params: LinkParams,
+    ) -> Result<Self, GridAccessError> {
+        let node_count = Dim::try_from(adjacency.len())
+            .map_err(|_| GridAccessError::TooManyNodes(adjacency.len()))?;
+        let mut graph = Self::new(node_count);
+        graph.ports = adjacency.iter().map(Vec::len).collect();
+
+        for (src, neighbours) in adjacency.iter().enumerate() {
+            let mut seen: HashMap<NodeId, usize> = HashMap::new();
+            for (src_port, &dest) in neighbours.iter().enumerate() {
+                let back = adjacency
+                    .get(dest)
+                    .ok_or(GridAccessError::InvalidNodeId(dest))?;
+                let nth = seen.entry(dest).or_default();
+                let dest_port = match back
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &back)| back == src)
+                    .nth(*nth)
+                {
+                    Some((port, _)) => port,
+                    None => graph.add_port(dest),
+                };
+                *nth += 1;
+
+                graph.links.push(Link {
+                    src,
+                    src_port,
+                    dest,
+                    dest_port,
+                    params,
+                    dateline: false,
+                });
+            }
+        }
+
+        Ok(graph)
+    }
+
+    // Bidirectional ring of node_count nodes, port 0 goes to the next node and port 1 back
+    // - Below 3 nodes next and back are the same node, 2 nodes get two parallel links each way and
+    // a single node two links to itself
+    pub fn ring(node_count: Dim, params: LinkParams) -> Self {
+        let count = node_count as usize;
+        let adjacency: Vec<Vec<NodeId>> = (0..count)
+            .map(|node| vec![(node + 1) % count, (node + count - 1) % count])
+            .collect();
+
+        Self::from_adjacency(&adjacency, params).expect("Ring only links nodes it has")
+    }
+
+    pub fn add_node(&mut self, pos: Coord) -> NodeId {
+        let node = self.coords.len();
+        self.coords.push(pos);
+        self.ports.push(0);
+        self.lookup.insert(pos, node);
+
+        node
+    }
+
+    pub fn set_coord(&mut self, node: NodeId, pos: Coord) {
+        self.lookup.remove(&self.coords[node]);
+        self.coords[node] = pos;
+        self.lookup.insert(pos, node);
+    }
+
+    // Adds a link each way between a and b on a fresh port of both nodes
+    pub fn connect(&mut self, a: NodeId, b: NodeId, params: LinkParams) {
+        let a_port = self.add_port(a);
+        let b_port = self.add_port(b);
+
+        for (src, src_port, dest, dest_port) in [(a, a_port, b, b_port), (b, b_port, a, a_port)] {
+            self.links.push(Link {
+                src,
+                src_port,
+                dest,
+                dest_port,
+                params,
+                dateline: false,
+            });
+        }
+    }
+
+    fn add_port(&mut self, node: NodeId) -> PortId {
+        self.ports[node] += 1;
+        self.ports[node] - 1
+    }
+}
+
+impl Topology for Graph {
+    fn node_count(&self) -> usize {
+        self.coords.len()
+    }
+
+    fn port_count(&self, node: NodeId) -> usize {
+        self.ports[node]
+    }
+
+    fn coord(&self, node: NodeId) -> Coord {
+        self.coords[node]
+    }
+
+    fn node_id(&self, pos: Coord) -> Result<NodeId, GridAccessError> {
+        self.lookup
+            .get(&pos)
+            .copied()
+            .ok_or(GridAccessError::InvalidNode(pos))
+    }
+
+    fn port_dir(&self, _node: NodeId, port: PortId) -> Direction {
+        Direction::Port(port)
+    }
+
+    fn links(&self) -> Vec<Link> {
+        self.links.clone()
+    }
+
+    fn route(&self, src: NodeId, dest: NodeId) -> Option<Vec<PortId>> {
+        // Breadth first search from src, remembering the link each node was first reached over
+        let mut reached_by: Vec<Option<&Link>> = vec![None; self.coords.len()];
+        let mut visited = vec![false; self.coords.len()];
+        let mut frontier = VecDeque::from([src]);
+        visited[src] = true;
+
+        while let Some(node) = frontier.pop_front() {
+            if node == dest {
+                break;
+            }
+
+            for link in self.links.iter().filter(|link| link.src == node) {
+                if !visited[link.dest] {
+                    visited[link.dest] = true;
+                    reached_by[link.dest] = Some(link);
+                    frontier.push_back(link.dest);
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut node = dest;
+        while let Some(link) = reached_by[node] {
+            path.push(link.src_port);
+            node = link.src;
+        }
+        if node != src {
+            return None;
+        }
+
+        path.reverse();
+        Some(path)
+    }
+}
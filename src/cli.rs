@@ -0,0 +1,516 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{Value, json};
+use thiserror::Error;
+
+#[cfg(feature = "tui")]
+use mesh_sim::Dashboard;
+use mesh_sim::{
+    Config, ConfigError, Cycle, Distribution, Event, Grid, Heatmap, Metric, NodeCommError, Packet,
+    PacketData, RenderError, Stats, Sweep, TraceError, TraceWriter, TrafficConfig, TrafficError,
+    open_trace, write_chrome_trace, write_vcd,
+};
+
+// Keeps a tiny --step from queueing up runs that never end
+const MAX_SWEEP_RATES: usize = 1000;
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+
+    #[error("{0}")]
+    Simulation(#[from] NodeCommError),
+
+    #[error("{0}")]
+    Traffic(#[from] TrafficError),
+
+    #[error("{0}")]
+    Trace(#[from] TraceError),
+
+    #[error("{0}")]
+    Render(#[from] RenderError),
+
+    #[error("Unable to write the output: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Usage(String),
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "mesh-sim",
+    version,
+    about = "Cycle accurate network on chip simulator"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Build the grid of a config, inject its traffic and run it to the end")]
+    Run(RunArgs),
+    #[command(
+        about = "Sweep the injection rate: run the traffic of a config at every rate, lowest first, until the network saturates"
+    )]
+    Sweep(SweepArgs),
+    #[command(about = "Summarize a trace, or simulate its packets again on the grid of a config")]
+    Replay(ReplayArgs),
+    #[command(about = "Describe the topology of a config without running anything")]
+    Inspect(InspectArgs),
+}
+
+// How summaries are printed
+#[derive(Copy, Clone, PartialEq, Default, ValueEnum, Debug)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Default, ValueEnum, Debug)]
+pub enum InspectFormat {
+    #[default]
+    Text,
+    // The config with every default filled in
+    Json,
+    Toml,
+    Dot,
+    Svg,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    #[arg(help = "TOML or JSON config, with a traffic section")]
+    pub config: PathBuf,
+    #[arg(long, help = "Seed of the traffic, replaces the one in the config")]
+    pub seed: Option<u64>,
+    #[arg(long, help = "Injection rate in packets per node per cycle")]
+    pub rate: Option<f64>,
+    #[arg(long, help = "Cycles during which packets are injected")]
+    pub cycles: Option<Cycle>,
+    #[arg(long, value_enum, default_value_t, help = "Format of the summary")]
+    pub format: Format,
+    #[arg(long, help = "Write the events as JSON Lines, or CSV for a .csv path")]
+    pub trace: Option<PathBuf>,
+    #[arg(long, help = "Write the events in Chrome trace format")]
+    pub chrome: Option<PathBuf>,
+    #[arg(long, help = "Write a VCD waveform, samples every cycle")]
+    pub vcd: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Print a throughput heatmap of the first layer after the summary"
+    )]
+    pub heatmap: bool,
+    #[cfg(feature = "tui")]
+    #[arg(long, help = "Follow the run on an interactive dashboard")]
+    pub dashboard: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    #[arg(help = "TOML or JSON config, its traffic section gives the pattern and process")]
+    pub config: PathBuf,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Injection rates to run in packets per node per cycle, between 0 and 1, lowest first"
+    )]
+    pub rates: Vec<f64>,
+    #[arg(
+        long,
+        default_value_t = 0.05,
+        help = "Lowest rate when --rates isn't given"
+    )]
+    pub from: f64,
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        help = "Highest rate when --rates isn't given"
+    )]
+    pub to: f64,
+    #[arg(
+        long,
+        default_value_t = 0.05,
+        help = "Rate step when --rates isn't given"
+    )]
+    pub step: f64,
+    #[arg(long, help = "Seed of the traffic, replaces the one in the config")]
+    pub seed: Option<u64>,
+    #[arg(long, default_value_t = 1000)]
+    pub warmup: Cycle,
+    #[arg(long, default_value_t = 1000)]
+    pub measure: Cycle,
+    #[arg(long, default_value_t = 2000)]
+    pub drain: Cycle,
+    #[arg(long, value_enum, default_value_t, help = "Format of the summary")]
+    pub format: Format,
+    #[arg(long, help = "Write the curve as CSV")]
+    pub csv: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    #[arg(help = "Trace written by run --trace")]
+    pub trace: PathBuf,
+    #[arg(
+        long,
+        help = "Inject the packets of the trace again on the grid of this config"
+    )]
+    pub config: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t, help = "Format of the summary")]
+    pub format: Format,
+    #[arg(long, help = "Write the events in Chrome trace format")]
+    pub chrome: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    #[arg(help = "TOML or JSON config")]
+    pub config: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: InspectFormat,
+}
+
+// Runs a command, anything meant for the user goes to out
+pub fn execute(cli: Cli, out: &mut impl Write) -> Result<(), CliError> {
+    match cli.command {
+        Command::Run(args) => run(args, out),
+        Command::Sweep(args) => sweep(args, out),
+        Command::Replay(args) => replay(args, out),
+        Command::Inspect(args) => inspect(args, out),
+    }
+}
+
+fn run(args: RunArgs, out: &mut impl Write) -> Result<(), CliError> {
+    let mut config = Config::load(&args.config)?;
+    let mut traffic = config.traffic.take().ok_or_else(|| {
+        CliError::Usage(format!(
+            "Nothing to run, {} has no traffic section",
+            args.config.display()
+        ))
+    })?;
+    traffic.seed = args.seed.unwrap_or(traffic.seed);
+    traffic.rate = args.rate.unwrap_or(traffic.rate);
+    traffic.cycles = args.cycles.unwrap_or(traffic.cycles);
+    config.traffic = Some(traffic.clone());
+
+    let (mut grid, mut event_rx) = Grid::from_config(&config)?;
+    if args.vcd.is_some() {
+        grid.set_sample_interval(Some(1));
+    }
+    traffic.generator().inject(&mut grid, 0..traffic.cycles)?;
+
+    #[cfg(feature = "tui")]
+    if args.dashboard {
+        if args.trace.is_some() || args.chrome.is_some() {
+            return Err(CliError::Usage(
+                "The dashboard can't be combined with --trace or --chrome".to_string(),
+            ));
+        }
+        let stats = Dashboard::new(event_rx).run(&mut grid)?;
+        return finish_run(&args, &grid, &stats, out);
+    }
+
+    grid.run()?;
+    let mut events = Vec::new();
+    while let Ok(event) = event_rx.try_recv() {
+        events.push(event);
+    }
+
+    if let Some(path) = &args.trace {
+        let mut writer = TraceWriter::create(path)?;
+        for event in &events {
+            writer.write(event)?;
+        }
+        writer.finish()?;
+    }
+    if let Some(path) = &args.chrome {
+        write_chrome_trace(&events, BufWriter::new(File::create(path)?))?;
+    }
+
+    let mut stats = Stats::new();
+    events.iter().for_each(|event| stats.record(event));
+    finish_run(&args, &grid, &stats, out)
+}
+
+fn finish_run(
+    args: &RunArgs,
+    grid: &Grid,
+    stats: &Stats,
+    out: &mut impl Write,
+) -> Result<(), CliError> {
+    if let Some(path) = &args.vcd {
+        write_vcd(grid.time_series(), BufWriter::new(File::create(path)?))?;
+    }
+
+    write_summary(stats, args.format, out)?;
+    if args.heatmap && args.format == Format::Text {
+        write!(
+            out,
+            "{}",
+            Heatmap::new(Metric::Throughput).render(grid, stats)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn sweep(args: SweepArgs, out: &mut impl Write) -> Result<(), CliError> {
+    let config = Config::load(&args.config)?;
+    let traffic = config.traffic.clone().unwrap_or_default();
+    let rates = sweep_rates(&args)?;
+
+    let topology = config.topology;
+    let mut sweep = Sweep::new(
+        (topology.width, topology.height, topology.depth),
+        traffic.pattern,
+        rates,
+    );
+    sweep.process = traffic.process;
+    sweep.flits = traffic.packet_flits;
+    sweep.seed = args.seed.unwrap_or(traffic.seed);
+    (sweep.warmup, sweep.measure, sweep.drain) = (args.warmup, args.measure, args.drain);
+
+    let (mut grid, _event_rx) = Grid::from_config(&config)?;
+    let curve = sweep.run(&mut grid)?;
+    if let Some(path) = &args.csv {
+        curve.write_csv(BufWriter::new(File::create(path)?))?;
+    }
+
+    match args.format {
+        Format::Text => {
+            writeln!(
+                out,
+                "{:>8} {:>8} {:>8} {:>9} {:>6} {:>6} {:>8}",
+                "rate", "offered", "accepted", "latency", "p50", "p99", "packets"
+            )?;
+            for point in &curve.points {
+                writeln!(
+                    out,
+                    "{:>8.3} {:>8.4} {:>8.4} {:>9.2} {:>6} {:>6} {:>8}{}",
+                    point.rate,
+                    point.offered,
+                    point.accepted,
+                    point.latency,
+                    point.p50,
+                    point.p99,
+                    point.packets,
+                    if point.saturated { " saturated" } else { "" }
+                )?;
+            }
+            let saturated = curve.points.last().is_some_and(|point| point.saturated);
+            match curve.saturation {
+                Some(rate) => writeln!(out, "saturates above {rate:.3} packets/node/cycle")?,
+                None if saturated => writeln!(out, "saturated at the lowest rate")?,
+                None => writeln!(out, "never saturated")?,
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &curve).map_err(io::Error::from)?;
+            writeln!(out)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Rates the sweep runs, checked before anything is simulated
+fn sweep_rates(args: &SweepArgs) -> Result<Vec<f64>, CliError> {
+    let valid = |rate: f64| rate.is_finite() && (0.0..=1.0).contains(&rate);
+    if !args.rates.is_empty() {
+        if let Some(rate) = args.rates.iter().find(|&&rate| !valid(rate)) {
+            return Err(CliError::Usage(format!(
+                "--rates has {rate}, every rate has to be between 0 and 1"
+            )));
+        }
+        if args.rates.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(CliError::Usage(
+                "--rates has to be in increasing order".to_string(),
+            ));
+        }
+        return Ok(args.rates.clone());
+    }
+
+    if !valid(args.from) || !valid(args.to) || args.from > args.to {
+        return Err(CliError::Usage(
+            "--from and --to have to be between 0 and 1 and --from at most --to".to_string(),
+        ));
+    }
+    // Counted in steps so rounding can't drop the last rate
+    let steps = (args.to - args.from) / args.step + 1e-9;
+    if !(args.step > 0.0 && steps < MAX_SWEEP_RATES as f64) {
+        return Err(CliError::Usage(format!(
+            "--step has to be positive and give at most {MAX_SWEEP_RATES} rates"
+        )));
+    }
+    Ok((0..=steps.floor() as usize)
+        .map(|step| args.from + step as f64 * args.step)
+        .collect())
+}
+
+fn replay(args: ReplayArgs, out: &mut impl Write) -> Result<(), CliError> {
+    let mut events = open_trace(&args.trace)?;
+
+    if let Some(path) = &args.config {
+        // Every packet that arrived is sent again with its length and class from the cycle it was
+        // created on, in the order it was first injected, packets the trace never saw arrive are
+        // lost
+        let config = Config::load(path)?;
+        let (mut grid, mut event_rx) = Grid::from_config(&config)?;
+        let mut packets: Vec<_> = events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::PacketArrived {
+                    id,
+                    src,
+                    dest,
+                    created,
+                    flits,
+                    class,
+                    ..
+                } => Some((created, id, src, dest, flits, class)),
+                _ => None,
+            })
+            .collect();
+        packets.sort_by_key(|&(created, id, ..)| (created, id));
+        for (created, _, src, dest, flits, class) in packets {
+            let packet = Packet::new(PacketData::Default, src, dest)
+                .with_flits(flits)
+                .with_class(class);
+            grid.send_packet_at(packet, created)?;
+        }
+        grid.run()?;
+
+        events.clear();
+        while let Ok(event) = event_rx.try_recv() {
+            events.push(event);
+        }
+    }
+
+    if let Some(path) = &args.chrome {
+        write_chrome_trace(&events, BufWriter::new(File::create(path)?))?;
+    }
+
+    let mut stats = Stats::new();
+    events.iter().for_each(|event| stats.record(event));
+    write_summary(&stats, args.format, out)
+}
+
+fn inspect(args: InspectArgs, out: &mut impl Write) -> Result<(), CliError> {
+    let config = Config::load(&args.config)?;
+    let (grid, _event_rx) = Grid::from_config(&config)?;
+
+    match args.format {
+        InspectFormat::Text => {
+            let topology = config.topology;
+            let nodes =
+                topology.width as usize * topology.height as usize * topology.depth as usize;
+            let params = config.nodes.params();
+            writeln!(
+                out,
+                "{:?} of {}x{}x{}, {nodes} nodes and {} links",
+                topology.kind,
+                topology.width,
+                topology.height,
+                topology.depth,
+                grid.link_usage().len()
+            )?;
+            writeln!(
+                out,
+                "links: latency {} bandwidth {} credit latency {}",
+                config.links.latency, config.links.bandwidth, config.links.credit_latency
+            )?;
+            writeln!(
+                out,
+                "nodes: tx {} rx {} flits/cycle, link buffers {} inner buffers {} flits, {} overridden",
+                params.tx_rate,
+                params.rx_rate,
+                params.link_buffer,
+                params.inner_buffer,
+                config.nodes.overrides.len()
+            )?;
+            writeln!(
+                out,
+                "router: {}, {} virtual channels ({:?}), {} routing",
+                match config.router.pipeline {
+                    Some(_) => "pipelined",
+                    None => "single cycle",
+                },
+                config.router.virtual_channels,
+                config.router.vc_selection,
+                grid.routing().name()
+            )?;
+            match &config.traffic {
+                Some(TrafficConfig {
+                    pattern,
+                    process,
+                    rate,
+                    cycles,
+                    ..
+                }) => writeln!(
+                    out,
+                    "traffic: {pattern:?} {process:?} at {rate} packets/node/cycle for {cycles} cycles"
+                )?,
+                None => writeln!(out, "traffic: none")?,
+            }
+        }
+        InspectFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &config).map_err(io::Error::from)?;
+            writeln!(out)?;
+        }
+        InspectFormat::Toml => {
+            let toml = toml::to_string_pretty(&config).map_err(|err| {
+                CliError::Usage(format!("The config can't be written as TOML: {err}"))
+            })?;
+            write!(out, "{toml}")?;
+        }
+        InspectFormat::Dot => grid.write_dot(out)?,
+        InspectFormat::Svg => grid.write_svg(out)?,
+    }
+
+    Ok(())
+}
+
+fn write_summary(stats: &Stats, format: Format, out: &mut impl Write) -> Result<(), CliError> {
+    match format {
+        Format::Text => write!(out, "{stats}")?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &summary(stats)).map_err(io::Error::from)?;
+            writeln!(out)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn summary(stats: &Stats) -> Value {
+    let distribution = |values: &Distribution| {
+        json!({
+            "mean": values.mean(),
+            "min": values.min(),
+            "max": values.max(),
+            "p50": values.percentile(50.0),
+            "p90": values.percentile(90.0),
+            "p99": values.percentile(99.0),
+        })
+    };
+
+    json!({
+        "packets": stats.packets_accepted(),
+        "arrived": stats.packets_arrived(),
+        "in_network": stats.in_network(),
+        "cycles": stats.cycles(),
+        "throughput": stats.throughput(),
+        "latency": distribution(stats.latency()),
+        "queuing_delay": distribution(stats.queuing_delay()),
+        "network_latency": distribution(stats.network_latency()),
+        "hops": distribution(stats.hops()),
+    })
+}
@@ -1,23 +1,127 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fmt;
 
-use crate::comm::transfer::{Direction, calc_path};
+use serde::{Deserialize, Serialize};
 
+use crate::arch::coord::Coord;
+use crate::arch::router::StageTiming;
+use crate::arch::topology::PortId;
+use crate::comm::transfer::Direction;
+use crate::sim::engine::Cycle;
+
+// Every event carries the simulated cycle it happened on
+// Serialized tagged with the name of the event, {"event":"PacketSent","id":0,...}
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(tag = "event")]
 pub enum Event {
+    // Fired once the tail flit is in, created and hops let the latency be split up without
+    // following the packet through the other events
+    // - flits and class are what the packet was sent with, enough to inject it again
     PacketArrived {
         id: usize,
-        at: (u8, u8),
-        dest: (u8, u8),
+        at: Coord,
+        src: Coord,
+        dest: Coord,
+        created: Cycle,
+        hops: usize,
+        flits: usize,
+        class: usize,
+        cycle: Cycle,
     },
     PacketReceived {
         id: usize,
         recv_dir: Direction,
-        at: (u8, u8),
+        at: Coord,
+        cycle: Cycle,
     },
     PacketSent {
         id: usize,
         send_dir: Direction,
-        from: (u8, u8),
+        // Virtual channel it was allocated on the link
+        vc: usize,
+        from: Coord,
+        cycle: Cycle,
     },
+    // The flit at the head of a queue ran out of credits for send_dir on since and finally left on
+    // cycle
+    CreditStall {
+        id: usize,
+        at: Coord,
+        send_dir: Direction,
+        vc: usize,
+        since: Cycle,
+        cycle: Cycle,
+    },
+    // Only emitted when the grid runs the router pipeline model, reported on the cycle the packet
+    // is granted its output
+    PacketRouted {
+        id: usize,
+        at: Coord,
+        stages: Vec<StageTiming>,
+        cycle: Cycle,
+    },
+}
+
+impl Event {
+    pub fn cycle(&self) -> Cycle {
+        match self {
+            Event::PacketArrived { cycle, .. }
+            | Event::PacketReceived { cycle, .. }
+            | Event::PacketSent { cycle, .. }
+            | Event::CreditStall { cycle, .. }
+            | Event::PacketRouted { cycle, .. } => *cycle,
+        }
+    }
+}
+
+// One line per event for logs
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::PacketArrived {
+                id,
+                at,
+                src,
+                hops,
+                cycle,
+                ..
+            } => write!(
+                f,
+                "{cycle}: packet {id} arrived at {at} from {src} in {hops} hops"
+            ),
+            Event::PacketReceived {
+                id,
+                recv_dir,
+                at,
+                cycle,
+            } => write!(f, "{cycle}: packet {id} received at {at} from {recv_dir:?}"),
+            Event::PacketSent {
+                id,
+                send_dir,
+                vc,
+                from,
+                cycle,
+            } => write!(
+                f,
+                "{cycle}: packet {id} sent from {from} to {send_dir:?} on vc {vc}"
+            ),
+            Event::CreditStall {
+                id,
+                at,
+                send_dir,
+                vc,
+                since,
+                cycle,
+            } => write!(
+                f,
+                "{cycle}: packet {id} at {at} waited {} cycles for credits to {send_dir:?} vc {vc}",
+                cycle - since
+            ),
+            Event::PacketRouted { id, at, cycle, .. } => {
+                write!(f, "{cycle}: packet {id} routed at {at}")
+            }
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -32,10 +136,25 @@ pub enum PacketData {
 pub struct MetaData {
     pub id: usize,
     pub dir: Direction,
-    pub path: Vec<Direction>,
+    // Output port taken at every hop, either the whole source route from the start or filled in
+    // hop by hop by the routing algorithm
+    pub path: Vec<PortId>,
     pub path_step: usize,
-    pub cur_pos: (u8, u8),
-    pub dest_pos: (u8, u8),
+    pub src_pos: Coord,
+    pub cur_pos: Coord,
+    pub dest_pos: Coord,
+    // Cycle the packet was handed to the network, anything until it leaves the source is queuing
+    pub created: Cycle,
+    // Cycle the packet entered the router it is currently in
+    pub hop_start: Cycle,
+    // Message class, picks the virtual channels with VcSelection::Class
+    pub class: usize,
+    // Datelines crossed since the last turn, picks the virtual channels with VcSelection::Phase
+    pub phase: usize,
+    // Virtual channel the packet is buffered in, or travelling on
+    pub vc: usize,
+    // Length of the packet in flits, at least one
+    pub flits: usize,
 }
 
 // Might not need to derive default here, look into deleting in the future
@@ -46,21 +165,101 @@ pub struct Packet {
 }
 
 impl Packet {
-    fn get_id() -> usize {
-        static PACKET_COUNTER: AtomicUsize = AtomicUsize::new(0);
-        PACKET_COUNTER.fetch_add(1, Ordering::Relaxed)
-    }
-
-    pub fn new(data: PacketData, src_pos: (u8, u8), dest_pos: (u8, u8)) -> Self {
+    // The id is handed out by the grid when the packet is injected so that it only depends on the
+    // injection order of that grid
+    pub fn new(data: PacketData, src_pos: impl Into<Coord>, dest_pos: impl Into<Coord>) -> Self {
+        let src_pos = src_pos.into();
         let header = MetaData {
-            id: Packet::get_id(),
+            id: 0,
             dir: Direction::Init,
             path: Vec::new(),
             path_step: 0,
+            src_pos,
             cur_pos: src_pos,
-            dest_pos,
+            dest_pos: dest_pos.into(),
+            created: 0,
+            hop_start: 0,
+            class: 0,
+            phase: 0,
+            vc: 0,
+            flits: 1,
         };
 
         Self { header, data }
     }
+
+    pub fn with_class(mut self, class: usize) -> Self {
+        self.header.class = class;
+        self
+    }
+
+    pub fn with_flits(mut self, flits: usize) -> Self {
+        self.header.flits = flits.max(1);
+        self
+    }
+
+    pub fn data(&self) -> &PacketData {
+        &self.data
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FlitKind {
+    Head,
+    Body,
+    Tail,
+    // Packet that fits in a single flit, head and tail at once
+    Single,
+}
+
+impl FlitKind {
+    pub fn is_head(self) -> bool {
+        matches!(self, FlitKind::Head | FlitKind::Single)
+    }
+
+    pub fn is_tail(self) -> bool {
+        matches!(self, FlitKind::Tail | FlitKind::Single)
+    }
+}
+
+// Unit that actually moves through the buffers and over the links
+// - The head flit carries the packet and reserves the path, body and tail flits just follow it
+// over the same virtual channels and the tail releases them
+#[derive(Debug)]
+pub struct Flit {
+    pub id: usize,
+    pub kind: FlitKind,
+    // Virtual channel the flit is buffered in, or travelling on
+    pub vc: usize,
+    pub packet: Option<Packet>,
+}
+
+impl Flit {
+    // Splits the packet into header.flits flits
+    pub fn segment(packet: Packet) -> Vec<Flit> {
+        let (id, vc, len) = (packet.header.id, packet.header.vc, packet.header.flits);
+        let head = Flit {
+            id,
+            kind: if len > 1 {
+                FlitKind::Head
+            } else {
+                FlitKind::Single
+            },
+            vc,
+            packet: Some(packet),
+        };
+
+        std::iter::once(head)
+            .chain((1..len).map(|i| Flit {
+                id,
+                kind: if i + 1 < len {
+                    FlitKind::Body
+                } else {
+                    FlitKind::Tail
+                },
+                vc,
+                packet: None,
+            }))
+            .collect()
+    }
 }
@@ -1,22 +1,89 @@
-use std::time::Duration;
+use std::collections::VecDeque;
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::select;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::mpsc::error::SendError;
-use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
 
-use crate::comm::packet::{Event, Packet};
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::GridAccessError;
+use crate::arch::node::MeshNode;
+use crate::arch::router::{RouterPipeline, VirtualChannels};
+use crate::arch::routing::RoutingAlgorithm;
+use crate::arch::topology::PortId;
+use crate::comm::packet::{Event, Flit, MetaData, Packet};
+use crate::sim::deadlock::Deadlock;
+use crate::sim::engine::Cycle;
 
-#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub const PORT_COUNT: usize = 6;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Debug)]
 pub enum Direction {
     Up,
     Down,
     Left,
     Right,
+    // Vertical links of a stacked mesh, Above goes one layer up (z + 1)
+    Above,
+    Below,
+    // Port of a node in a topology that isn't a grid
+    Port(PortId),
     #[default]
     Init,
 }
 
+impl Direction {
+    // Port order of a grid node, index() is the inverse of this
+    pub const PORTS: [Direction; PORT_COUNT] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+        Direction::Above,
+        Direction::Below,
+    ];
+
+    pub fn index(self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+            Direction::Above => 4,
+            Direction::Below => 5,
+            Direction::Port(port) => port,
+            Direction::Init => unreachable!("Init isn't a port"),
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::Above => Direction::Below,
+            Direction::Below => Direction::Above,
+            Direction::Port(port) => Direction::Port(port),
+            Direction::Init => Direction::Init,
+        }
+    }
+
+    pub fn is_vertical(self) -> bool {
+        matches!(self, Direction::Above | Direction::Below)
+    }
+
+    // Dimension a grid direction moves along, None for ports of other topologies
+    pub fn axis(self) -> Option<usize> {
+        match self {
+            Direction::Left | Direction::Right => Some(0),
+            Direction::Up | Direction::Down => Some(1),
+            Direction::Above | Direction::Below => Some(2),
+            Direction::Port(_) | Direction::Init => None,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum SendDirError {
     #[error("Tried to send a packet out of bounds (y value below 0)")]
@@ -34,36 +101,79 @@ pub enum NodeCommError {
     // Make sure this zero actually passes the error string defined above
     #[error("{0}")]
     SendDirError(SendDirError),
+    #[error("{0}")]
+    GridAccess(#[from] GridAccessError),
+    #[error("No route from {src} to {dest}")]
+    NoRoute { src: Coord, dest: Coord },
+    #[error("{0}")]
+    Deadlock(Deadlock),
+    // Nothing left to process but flits that can't move, without a deadlock cycle between them
     #[error(
-        "Channel unable to send packets: Check corresponding Receiver is alive and the channel is open"
+        "Stalled on cycle {cycle} with flits stuck in {nodes} nodes and no deadlock between them"
     )]
-    SendErrorPacket(#[from] SendError<Packet>),
+    Stalled { cycle: Cycle, nodes: usize },
     #[error(
         "Channel unable to send events: Check corresponding Receiver is alive and the channel is open"
     )]
     SendErrorEvent(#[from] SendError<Event>),
 }
 
-// We can be receiving data while processing/sending data - account for this
-// Maybe a separate task waiting for data to come in and other processing data if available
-
-// Thinking through this:
-//  - There should be a separate async function that receives the packets as they come in and
-//  emplaces them in the buffer
-//      - How should we handle many packets coming in at once? Probably just a select! macro that
-//      reads into the input buffer immediately then selects again
-//  - We need a way to process this data and send it out, but imagine that the data processing at
-//  each node could be computationally intensive
-//  - Could launch a blocking thread to do the computation
-//      - Actually, this should depend on if the node is a Routing or Processing node
-//      - A processing node can do everything a routing node can (redirect) but also process
-//      packets in some way
-//      - For now we'll simulate it as if every node in the middle is simply routing, but a V2
-//      might simulate both router and cpu running at the same time -> local channel?
-
-pub fn calc_path(mut cur_pos: (u8, u8), dest_pos: (u8, u8)) -> Vec<Direction> {
-    let mut x_delta = dest_pos.0 as i16 - cur_pos.0 as i16;
-    let mut y_delta = dest_pos.1 as i16 - cur_pos.1 as i16;
+// Shortest path on a torus, each dimension goes over the wrap link when that is strictly shorter
+pub fn calc_torus_path(
+    cur_pos: Coord,
+    dest_pos: Coord,
+    (width, height): (Dim, Dim),
+) -> Vec<Direction> {
+    let x_delta = wrap_delta(cur_pos.x, dest_pos.x, width);
+    let y_delta = wrap_delta(cur_pos.y, dest_pos.y, height);
+
+    path_from_deltas(x_delta, y_delta)
+}
+
+// Dimension ordered routing for stacked meshes: all the x hops, then y, then z
+// - wrap takes the wrap links in x and y like calc_torus_path does
+pub fn calc_path_3d(
+    cur_pos: Coord,
+    dest_pos: Coord,
+    (width, height): (Dim, Dim),
+    wrap: bool,
+) -> Vec<Direction> {
+    let (x_delta, y_delta) = if wrap {
+        (
+            wrap_delta(cur_pos.x, dest_pos.x, width),
+            wrap_delta(cur_pos.y, dest_pos.y, height),
+        )
+    } else {
+        (
+            dest_pos.x as i64 - cur_pos.x as i64,
+            dest_pos.y as i64 - cur_pos.y as i64,
+        )
+    };
+    let z_delta = dest_pos.z as i64 - cur_pos.z as i64;
+
+    let hops = |delta: i64, neg: Direction, pos: Direction| {
+        let dir = if delta < 0 { neg } else { pos };
+        std::iter::repeat_n(dir, delta.unsigned_abs() as usize)
+    };
+
+    hops(x_delta, Direction::Left, Direction::Right)
+        .chain(hops(y_delta, Direction::Up, Direction::Down))
+        .chain(hops(z_delta, Direction::Below, Direction::Above))
+        .collect()
+}
+
+fn wrap_delta(cur: Dim, dest: Dim, size: Dim) -> i64 {
+    let delta = dest as i64 - cur as i64;
+    let size = size as i64;
+
+    if delta.abs() * 2 > size {
+        delta - delta.signum() * size
+    } else {
+        delta
+    }
+}
+
+fn path_from_deltas(mut x_delta: i64, mut y_delta: i64) -> Vec<Direction> {
     let mut path_vec = Vec::new();
     let mut neg_first_greedy;
 
@@ -73,9 +183,7 @@ pub fn calc_path(mut cur_pos: (u8, u8), dest_pos: (u8, u8)) -> Vec<Direction> {
             neg_first_greedy = Direction::Down;
         } else if x_delta > 0 && y_delta < 0 {
             neg_first_greedy = Direction::Right;
-        } else if x_delta < 0 && y_delta > 0 {
-            neg_first_greedy = Direction::Left;
-        } else if x_delta < 0 && y_delta < 0 {
+        } else if x_delta < 0 && y_delta != 0 {
             neg_first_greedy = Direction::Left;
         } else if x_delta == 0 && y_delta < 0 {
             neg_first_greedy = Direction::Up;
@@ -90,13 +198,11 @@ pub fn calc_path(mut cur_pos: (u8, u8), dest_pos: (u8, u8)) -> Vec<Direction> {
         }
 
         if neg_first_greedy == Direction::Left || neg_first_greedy == Direction::Right {
-            chosen_path = vec![neg_first_greedy; x_delta.abs() as usize];
+            chosen_path = vec![neg_first_greedy; x_delta.unsigned_abs() as usize];
             x_delta = 0;
-            cur_pos.0 = cur_pos.0 + x_delta as u8;
         } else {
-            chosen_path = vec![neg_first_greedy; y_delta.abs() as usize];
+            chosen_path = vec![neg_first_greedy; y_delta.unsigned_abs() as usize];
             y_delta = 0;
-            cur_pos.1 = cur_pos.1 + y_delta as u8;
         }
 
         path_vec.append(&mut chosen_path);
@@ -105,193 +211,376 @@ pub fn calc_path(mut cur_pos: (u8, u8), dest_pos: (u8, u8)) -> Vec<Direction> {
     path_vec
 }
 
-// The flow for the send/receive packet functions should be:
-// - select! selects a channel to read a packet from via the rx await from cardinal directions
-// - A receive_packet() task is launched to process the packet and redirect it
-//  - This at some point launches a send_packet() task to enqueue it in a new channel
-
-// This should be async so that a task can be spawned with the purpose of routing a packet
-
-// Instead of MeshNode objects owning their Receivers, should receive_packets take in the
-// Receivers and constantly spin as as a tokio task?
-pub async fn receive_packets(
-    // node: Arc<MeshNode>,
-    mut rx_up: Option<Receiver<Packet>>,
-    mut rx_down: Option<Receiver<Packet>>,
-    mut rx_left: Option<Receiver<Packet>>,
-    mut rx_right: Option<Receiver<Packet>>,
-    inner_tx_up: &Sender<Packet>,
-    inner_tx_down: &Sender<Packet>,
-    inner_tx_left: &Sender<Packet>,
-    inner_tx_right: &Sender<Packet>,
+// The flow for the send/receive packet functions is now driven by the simulation clock:
+// - Each time a node is serviced its receive stage moves flits out of the link buffers into the
+// inner buffers (or retires them if their packet has reached its destination)
+// - Then the send stage picks flits from the inner buffers and the local queue and puts them on
+// the outgoing links, the grid takes care of delivering them to the neighbour
+// - Packets move wormhole style: the head flit is routed and allocated an output channel, the
+// rest of the packet streams after it and the tail frees the channel again, so one packet can be
+// spread over several routers
+// - Both stages are plain functions over the node state so the same inputs always give the same
+// outputs, no matter how busy the host is
+
+// Returns the port and virtual channel of every link buffer slot freed this cycle so the network
+// can send the credits back upstream
+pub fn receive_packets(
+    node: &mut MeshNode,
     event_tx: &UnboundedSender<Event>,
-    tx_rate: u64,
-) -> Result<(), NodeCommError> {
-    loop {
-        // let node_clone = Arc::clone(&node);
-        tokio::time::sleep(std::time::Duration::from_millis((1 / tx_rate) * 1000)).await;
-        select! {
-            Some(mut packet) = async {
-                if let Some(rx) = rx_up.as_mut() {
-                    rx.recv().await
-                } else {
-                    std::future::pending().await
-                }
-            } => {
-                packet.header.cur_pos = (packet.header.cur_pos.0, packet.header.cur_pos.1 + 1);
-                if packet.header.path_step == packet.header.path.len() {
-                        event_tx.send(Event::PacketArrived { id: packet.header.id, at: packet.header.cur_pos, dest: packet.header.dest_pos })?;
-                        continue;
+    cycle: Cycle,
+) -> Result<Vec<(PortId, usize)>, NodeCommError> {
+    // Every virtual channel of every port is an input of its own
+    let vc_count = node.vc_count();
+    let input_count = node.port_count() * vc_count;
+    let mut freed = Vec::new();
+    let mut budget = node.rx_rate;
+    let mut progress = true;
+
+    while budget > 0 && progress {
+        progress = false;
+
+        for offset in 0..input_count {
+            if budget == 0 {
+                break;
+            }
+
+            let input = (node.recv_rr + offset) % input_count;
+            let (port, vc) = (input / vc_count, input % vc_count);
+            let Some(flit) = node.link_buffers[port][vc].front() else {
+                continue;
+            };
+            let arrived = match &flit.packet {
+                Some(packet) => packet.header.dest_pos == node.pos(),
+                None => node.ejecting[port][vc].is_some(),
+            };
+            if !arrived && node.inner_buffers[port][vc].len() >= node.inner_buffer {
+                continue;
+            }
+
+            if let Some(mut flit) = node.link_buffers[port][vc].pop_front() {
+                if let Some(packet) = &mut flit.packet {
+                    packet.header.cur_pos = node.pos();
+                    packet.header.hop_start = cycle;
+                    if !arrived {
+                        event_tx.send(Event::PacketReceived {
+                            id: packet.header.id,
+                            recv_dir: node.ports[port],
+                            at: packet.header.cur_pos,
+                            cycle,
+                        })?;
+                    }
                 }
-                event_tx.send(Event::PacketReceived { id: packet.header.id, recv_dir: Direction::Up, at: packet.header.cur_pos })?;
-                inner_tx_up.send(packet).await?;
-            },
-            Some(mut packet) = async {
-                if let Some(rx) = rx_down.as_mut() {
-                    rx.recv().await
+
+                if arrived {
+                    // The packet is only complete once its tail is in
+                    let packet = flit.packet.or_else(|| node.ejecting[port][vc].take());
+                    if flit.kind.is_tail() {
+                        let packet = packet.expect("Tail flit should follow its head");
+                        event_tx.send(Event::PacketArrived {
+                            id: packet.header.id,
+                            at: node.pos(),
+                            src: packet.header.src_pos,
+                            dest: packet.header.dest_pos,
+                            created: packet.header.created,
+                            hops: packet.header.path_step,
+                            flits: packet.header.flits,
+                            class: packet.header.class,
+                            cycle,
+                        })?;
+                    } else {
+                        node.ejecting[port][vc] = packet;
+                    }
                 } else {
-                    std::future::pending().await
+                    node.inner_buffers[port][vc].push_back(flit);
                 }
-            } => {
-                packet.header.cur_pos = (packet.header.cur_pos.0, packet.header.cur_pos.1 - 1);
-                if packet.header.path_step == packet.header.path.len() {
-                        event_tx.send(Event::PacketArrived { id: packet.header.id, at: packet.header.cur_pos, dest: packet.header.dest_pos })?;
+
+                freed.push((port, vc));
+                budget -= 1;
+                progress = true;
+            }
+        }
+    }
+
+    node.recv_rr = (node.recv_rr + 1) % input_count.max(1);
+    Ok(freed)
+}
+
+// Sources are the inner buffers of every virtual channel of every port followed by the local queue
+pub fn source_queue(node: &MeshNode, source: usize) -> &VecDeque<Flit> {
+    let vc_count = node.vc_count();
+    if source < node.port_count() * vc_count {
+        &node.inner_buffers[source / vc_count][source % vc_count]
+    } else {
+        &node.local_queue
+    }
+}
+
+fn source_queue_mut(node: &mut MeshNode, source: usize) -> &mut VecDeque<Flit> {
+    let vc_count = node.vc_count();
+    if source < node.port_count() * vc_count {
+        &mut node.inner_buffers[source / vc_count][source % vc_count]
+    } else {
+        &mut node.local_queue
+    }
+}
+
+// A flit can only be sent with a credit for its channel on the output, see MeshNode::credits
+// - Returns the flits that were put on a link with the output port they left through, the
+// virtual channel is in the flit
+// - With a router pipeline a head can only leave once it has been buffered and routed, and the
+// switch passes at most one flit per input and per output each cycle
+pub fn send_packet(
+    node: &mut MeshNode,
+    routing: Option<&dyn RoutingAlgorithm>,
+    vcs: &VirtualChannels,
+    pipeline: Option<&RouterPipeline>,
+    event_tx: &UnboundedSender<Event>,
+    cycle: Cycle,
+) -> Result<Vec<(PortId, Flit)>, NodeCommError> {
+    let source_count = node.port_count() * node.vc_count() + 1;
+    let mut sent = Vec::new();
+    let mut budget = node.tx_rate;
+    let mut progress = true;
+    // How many more flits each outgoing link can take this cycle, shared by all its channels
+    let mut link_space: Vec<usize> = node
+        .out_links
+        .iter()
+        .map(|link| link.map_or(0, |link| link.params.bandwidth))
+        .collect();
+
+    while budget > 0 && progress {
+        progress = false;
+
+        for offset in 0..source_count {
+            if budget == 0 {
+                break;
+            }
+
+            let source = (node.send_rr + offset) % source_count;
+            let Some(flit) = source_queue(node, source).front() else {
+                continue;
+            };
+
+            let id = flit.id;
+
+            // Body and tail flits go wherever their head went
+            let (port, vc, phase) = match node.routes[source] {
+                Some((port, vc)) => {
+                    if link_space[port] == 0 {
                         continue;
-                }
-                event_tx.send(Event::PacketReceived { id: packet.header.id, recv_dir: Direction::Down, at: packet.header.cur_pos })?;
-                inner_tx_down.send(packet).await?;
-            },
-            Some(mut packet) = async {
-                if let Some(rx) = rx_left.as_mut() {
-                    rx.recv().await
-                } else {
-                    std::future::pending().await
-                }
-            } => {
-                packet.header.cur_pos = (packet.header.cur_pos.0 + 1, packet.header.cur_pos.1);
-                if packet.header.path_step == packet.header.path.len() {
-                        event_tx.send(Event::PacketArrived { id: packet.header.id, at: packet.header.cur_pos, dest: packet.header.dest_pos })?;
+                    }
+                    if node.credits[port][vc] == 0 {
+                        node.stalled[source].get_or_insert((cycle, port, vc));
                         continue;
+                    }
+                    (port, vc, None)
                 }
-                event_tx.send(Event::PacketReceived { id: packet.header.id, recv_dir: Direction::Left, at: packet.header.cur_pos })?;
-                inner_tx_left.send(packet).await?;
-            },
-            Some(mut packet) = async {
-                if let Some(rx) = rx_right.as_mut() {
-                    rx.recv().await
-                } else {
-                    std::future::pending().await
-                }
-            } => {
-                packet.header.cur_pos = (packet.header.cur_pos.0 - 1, packet.header.cur_pos.1);
-                if packet.header.path_step == packet.header.path.len() {
-                        event_tx.send(Event::PacketArrived { id: packet.header.id, at: packet.header.cur_pos, dest: packet.header.dest_pos })?;
+                None => {
+                    let header = &flit
+                        .packet
+                        .as_ref()
+                        .expect("Only a head flit can be waiting for a route")
+                        .header;
+                    let hop_start = header.hop_start;
+
+                    if let Some(pipeline) = pipeline
+                        && hop_start + pipeline.ready_delay() > cycle
+                    {
                         continue;
+                    }
+
+                    let candidates = output_candidates(node, header, routing)?;
+                    let (port, vc, phase) =
+                        match allocate_output(node, header, &candidates, vcs, &link_space) {
+                            Ok(allocated) => allocated,
+                            Err(Some((port, vc))) => {
+                                node.stalled[source].get_or_insert((cycle, port, vc));
+                                continue;
+                            }
+                            Err(None) => continue,
+                        };
+
+                    if let Some(pipeline) = pipeline {
+                        let link_latency = node.out_links[port]
+                            .expect("Path should only use ports with a link")
+                            .params
+                            .latency;
+                        event_tx.send(Event::PacketRouted {
+                            id,
+                            at: node.pos(),
+                            stages: pipeline.timings(hop_start, cycle, link_latency),
+                            cycle,
+                        })?;
+                    }
+
+                    (port, vc, Some(phase))
                 }
-                event_tx.send(Event::PacketReceived { id: packet.header.id, recv_dir: Direction::Right, at: packet.header.cur_pos })?;
-                inner_tx_right.send(packet).await?;
-            },
-        }
-    }
-}
+            };
 
-// This should be async so that a task can be spawned with the purpose enqueing the packet
-// - Might need to await while channel is being processed
-pub async fn send_packet(
-    // node: Arc<MeshNode>,
-    // mut packet: Packet,
-    tx_up: Option<&Sender<Packet>>,
-    tx_down: Option<&Sender<Packet>>,
-    tx_left: Option<&Sender<Packet>>,
-    tx_right: Option<&Sender<Packet>>,
-    tx_event: &UnboundedSender<Event>,
-    mut inner_rx_up: Receiver<Packet>,
-    mut inner_rx_down: Receiver<Packet>,
-    mut inner_rx_left: Receiver<Packet>,
-    mut inner_rx_right: Receiver<Packet>,
-    mut inner_rx_local: Receiver<Packet>,
-    rx_rate: u64,
-) -> Result<(), NodeCommError> {
-    loop {
-        tokio::time::sleep(Duration::from_millis((1 / rx_rate) * 1000)).await;
-        select! {
-            Some(inner_packet) = inner_rx_up.recv() => {
-                transmit_dir(inner_packet, tx_up, tx_down, tx_left, tx_right, &tx_event).await?
-            }
-            Some(inner_packet) = inner_rx_down.recv() => {
-                transmit_dir(inner_packet, tx_up, tx_down, tx_left, tx_right, &tx_event).await?
+            if let Some((since, port, vc)) = node.stalled[source].take() {
+                event_tx.send(Event::CreditStall {
+                    id,
+                    at: node.pos(),
+                    send_dir: node.ports[port],
+                    vc,
+                    since,
+                    cycle,
+                })?;
             }
-            Some(inner_packet) = inner_rx_left.recv() => {
-                transmit_dir(inner_packet, tx_up, tx_down, tx_left, tx_right, &tx_event).await?
-            }
-            Some(inner_packet) = inner_rx_right.recv() => {
-                transmit_dir(inner_packet, tx_up, tx_down, tx_left, tx_right, &tx_event).await?
-            },
-            Some(inner_packet) = inner_rx_local.recv() => {
-                transmit_dir(inner_packet, tx_up, tx_down, tx_left, tx_right, &tx_event).await?
+
+            if let Some(mut flit) = source_queue_mut(node, source).pop_front() {
+                flit.vc = vc;
+                if let Some(mut packet) = flit.packet.take() {
+                    if packet.header.path_step == packet.header.path.len() {
+                        packet.header.path.push(port);
+                    }
+                    packet.header.vc = vc;
+                    packet.header.phase = phase.unwrap_or(packet.header.phase);
+                    flit.packet = Some(transmit_dir(packet, node.ports[port], event_tx, cycle)?);
+                }
+
+                // The head holds the output channel until the tail has gone through
+                let hold = !flit.kind.is_tail();
+                node.routes[source] = hold.then_some((port, vc));
+                node.out_vc_held[port][vc] = hold;
+
+                // The switch only passes one flit per output each cycle with a pipeline
+                link_space[port] = if pipeline.is_some() {
+                    0
+                } else {
+                    link_space[port] - 1
+                };
+                node.credits[port][vc] -= 1;
+                sent.push((port, flit));
+                budget -= 1;
+                progress = true;
             }
         }
+
+        // Each input only gets one go at the switch per cycle
+        if pipeline.is_some() {
+            break;
+        }
     }
+
+    node.send_rr = (node.send_rr + 1) % source_count;
+    Ok(sent)
 }
 
-async fn transmit_dir(
+// Outputs the packet may leave the node through next, most preferred first
+// - A packet with a source route follows it, otherwise routing picks the output at every hop
+// - Fails on a packet with neither, like one injected without a path into a network without
+// routing
+pub fn output_candidates(
+    node: &MeshNode,
+    header: &MetaData,
+    routing: Option<&dyn RoutingAlgorithm>,
+) -> Result<Vec<PortId>, NodeCommError> {
+    match (header.path.get(header.path_step), routing) {
+        (Some(port), _) => Ok(vec![*port]),
+        (None, Some(routing)) => Ok(routing
+            .candidates(header.src_pos, node.pos(), header.dest_pos)
+            .into_iter()
+            .filter_map(|dir| node.ports.iter().position(|port| *port == dir))
+            .collect()),
+        (None, None) => Err(NodeCommError::NoRoute {
+            src: node.pos(),
+            dest: header.dest_pos,
+        }),
+    }
+}
+
+// Allocates a free virtual channel on every candidate output that can still take a flit this
+// cycle and takes the output whose channel has the most credits, ties go to the direction routing
+// prefers
+// - A channel held by another packet isn't free, whatever its credits
+// - Returns the output, the channel and the phase the packet is in on that link
+// - Fails with the output and channel the packet is stalled on if the only thing missing is a
+// credit, or None if every candidate is busy otherwise, the packet then waits at the head of its
+// queue either way
+fn allocate_output(
+    node: &MeshNode,
+    header: &MetaData,
+    candidates: &[PortId],
+    vcs: &VirtualChannels,
+    link_space: &[usize],
+) -> Result<(PortId, usize, usize), Option<(PortId, usize)>> {
+    let mut stalled = None;
+    let allocated = candidates
+        .iter()
+        .rev()
+        .filter(|port| link_space[**port] > 0)
+        .filter_map(|&port| {
+            let link = node.out_links[port].as_ref()?;
+            let phase = next_phase(header, node.ports[port], link.dateline);
+            let allowed = vcs.allowed(header, phase);
+            let unheld = |vc: &usize| !node.out_vc_held[port][*vc];
+            let credits: Vec<usize> = (0..node.vc_count())
+                .map(|vc| {
+                    if unheld(&vc) {
+                        node.credits[port][vc]
+                    } else {
+                        0
+                    }
+                })
+                .collect();
+
+            match vcs.allocate(allowed.clone(), &credits) {
+                Some(vc) => Some((port, vc, phase)),
+                None => {
+                    // Candidates are visited in reverse, so this ends up on the preferred one
+                    stalled = allowed
+                        .clone()
+                        .find(unheld)
+                        .map(|vc| (port, vc))
+                        .or(stalled);
+                    None
+                }
+            }
+        })
+        .max_by_key(|(port, vc, _)| node.credits[*port][*vc]);
+
+    allocated.ok_or(stalled)
+}
+
+// Phase of the packet on the link it leaves through in send_dir, it starts over when the packet
+// turns into another dimension and moves up when the link is a dateline
+pub fn next_phase(header: &MetaData, send_dir: Direction, dateline: bool) -> usize {
+    let turned = header.dir.axis().is_some() && header.dir.axis() != send_dir.axis();
+    let phase = if turned { 0 } else { header.phase };
+
+    phase + usize::from(dateline)
+}
+
+fn transmit_dir(
     mut packet: Packet,
-    tx_up: Option<&Sender<Packet>>,
-    tx_down: Option<&Sender<Packet>>,
-    tx_left: Option<&Sender<Packet>>,
-    tx_right: Option<&Sender<Packet>>,
+    send_dir: Direction,
     tx_event: &UnboundedSender<Event>,
-) -> Result<(), NodeCommError> {
-    packet.header.dir = packet.header.path[packet.header.path_step];
+    cycle: Cycle,
+) -> Result<Packet, NodeCommError> {
+    packet.header.dir = send_dir;
     packet.header.path_step += 1;
 
-    match packet.header.dir {
-        Direction::Up => {
-            tx_event.send(Event::PacketSent {
-                id: packet.header.id,
-                send_dir: Direction::Up,
-                from: packet.header.cur_pos,
-            })?;
-            tx_up
-                .expect("Up direction should've been supported")
-                .send(packet)
-                .await?
-        }
-        Direction::Down => {
-            tx_event.send(Event::PacketSent {
-                id: packet.header.id,
-                send_dir: Direction::Down,
-                from: packet.header.cur_pos,
-            })?;
-            tx_down
-                .expect("Down direction should've been supported")
-                .send(packet)
-                .await?
-        }
-        Direction::Left => {
-            tx_event.send(Event::PacketSent {
-                id: packet.header.id,
-                send_dir: Direction::Left,
-                from: packet.header.cur_pos,
-            })?;
-            tx_left
-                .expect("Left direction should've been supported")
-                .send(packet)
-                .await?
-        }
-        Direction::Right => {
-            tx_event.send(Event::PacketSent {
-                id: packet.header.id,
-                send_dir: Direction::Right,
-                from: packet.header.cur_pos,
-            })?;
-            tx_right
-                .expect("Right direction should've been supported")
-                .send(packet)
-                .await?
-        }
-        _ => unreachable!(),
-    }
+    tx_event.send(Event::PacketSent {
+        id: packet.header.id,
+        send_dir,
+        vc: packet.header.vc,
+        from: packet.header.cur_pos,
+        cycle,
+    })?;
+
+    Ok(packet)
+}
 
-    Ok(())
+// Earliest cycle after the current one on which a head flit at the front of one of the node's
+// queues finishes routing, only relevant with a router pipeline
+pub fn next_ready(node: &MeshNode, pipeline: &RouterPipeline, cycle: Cycle) -> Option<Cycle> {
+    node.inner_buffers
+        .iter()
+        .flatten()
+        .chain(std::iter::once(&node.local_queue))
+        .filter_map(|queue| queue.front()?.packet.as_ref())
+        .map(|packet| packet.header.hop_start + pipeline.ready_delay())
+        .filter(|ready| *ready > cycle)
+        .min()
 }
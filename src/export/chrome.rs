@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use serde_json::{Value, json};
+
+use crate::arch::coord::{Coord, Dim};
+use crate::comm::packet::Event;
+use crate::sim::engine::Cycle;
+
+// Everything goes in a single process, every node is a thread of it
+const PID: u64 = 1;
+
+// Writes events as Chrome Trace Event Format JSON that Perfetto and chrome://tracing can open
+// - Every node some event is at is a track, numbered in row major order of the nodes that are, so
+// idle nodes have none and the numbering depends on the traffic
+// - Every stay of a packet at a node is a slice on its track: from the cycle it was received (or
+// created at its source) to the cycle it was sent on (or arrived at its destination)
+// - Every hop is a flow arrow from the PacketSent to the PacketReceived of that packet, or to the
+// arrival slice for the last one
+// - Timestamps are cycles, shown as microseconds
+pub fn write_chrome_trace(events: &[Event], mut out: impl Write) -> io::Result<()> {
+    serde_json::to_writer(&mut out, &chrome_trace(events)).map_err(io::Error::from)?;
+    writeln!(out)
+}
+
+pub fn chrome_trace(events: &[Event]) -> Value {
+    // Creation cycles are only reported on arrival
+    let created: HashMap<usize, Cycle> = events
+        .iter()
+        .filter_map(|event| match *event {
+            Event::PacketArrived { id, created, .. } => Some((id, created)),
+            _ => None,
+        })
+        .collect();
+
+    // Tracks in row major order of the layers
+    let mut nodes: BTreeMap<(Dim, Dim, Dim), Coord> = BTreeMap::new();
+    let mut track = |pos: Coord| {
+        nodes.insert((pos.z, pos.y, pos.x), pos);
+        (pos.z, pos.y, pos.x)
+    };
+
+    // Node the packet is at and the cycle it got there, then the flow of the hop it is on and the
+    // cycle that hop started
+    let mut stays: HashMap<usize, (Coord, Cycle)> = HashMap::new();
+    let mut flows: HashMap<usize, (u64, Cycle)> = HashMap::new();
+    let mut flow_count = 0;
+    // Slices and flows keyed by track until the tracks are numbered
+    let mut pending: Vec<((Dim, Dim, Dim), Value)> = Vec::new();
+
+    for event in events {
+        match *event {
+            Event::PacketSent {
+                id,
+                send_dir,
+                vc,
+                from,
+                cycle,
+            } => {
+                let start = match stays.remove(&id) {
+                    Some((at, start)) if at == from => start,
+                    _ => created.get(&id).copied().unwrap_or(cycle),
+                };
+                pending.push((
+                    track(from),
+                    json!({
+                        "name": format!("packet {id}"),
+                        "cat": "hop",
+                        "ph": "X",
+                        "ts": start,
+                        "dur": cycle - start,
+                        "args": { "id": id, "out": format!("{send_dir:?}"), "vc": vc },
+                    }),
+                ));
+
+                flow_count += 1;
+                flows.insert(id, (flow_count, cycle));
+                pending.push((
+                    track(from),
+                    json!({
+                        "name": "hop",
+                        "cat": "flow",
+                        "ph": "s",
+                        "id": flow_count,
+                        "ts": cycle,
+                    }),
+                ));
+            }
+            Event::PacketReceived { id, at, cycle, .. } => {
+                stays.insert(id, (at, cycle));
+                if let Some((flow, _)) = flows.remove(&id) {
+                    pending.push((track(at), flow_end(flow, cycle)));
+                }
+            }
+            Event::PacketArrived {
+                id,
+                at,
+                src,
+                created,
+                cycle,
+                ..
+            } => {
+                // The destination only reports the tail, so the last hop lands on the cycle it
+                // was sent and the slice covers it crossing over
+                let start = match (stays.remove(&id), flows.remove(&id)) {
+                    (Some((stay, start)), _) if stay == at => start,
+                    (_, Some((flow, sent))) => {
+                        pending.push((track(at), flow_end(flow, sent)));
+                        sent
+                    }
+                    _ => created,
+                };
+                pending.push((
+                    track(at),
+                    json!({
+                        "name": format!("packet {id}"),
+                        "cat": "arrival",
+                        "ph": "X",
+                        "ts": start,
+                        "dur": cycle - start,
+                        "args": { "id": id, "src": src.to_string(), "latency": cycle - created },
+                    }),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let tids: HashMap<(Dim, Dim, Dim), usize> = nodes
+        .keys()
+        .enumerate()
+        .map(|(tid, &key)| (key, tid))
+        .collect();
+    let mut trace_events: Vec<Value> = nodes
+        .values()
+        .enumerate()
+        .flat_map(|(tid, pos)| {
+            [
+                json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": PID,
+                    "tid": tid,
+                    "args": { "name": format!("node {pos}") },
+                }),
+                json!({
+                    "name": "thread_sort_index",
+                    "ph": "M",
+                    "pid": PID,
+                    "tid": tid,
+                    "args": { "sort_index": tid },
+                }),
+            ]
+        })
+        .collect();
+
+    trace_events.extend(pending.into_iter().map(|(key, mut event)| {
+        event["pid"] = json!(PID);
+        event["tid"] = json!(tids[&key]);
+        event
+    }));
+
+    json!({
+        "traceEvents": trace_events,
+        "displayTimeUnit": "ms",
+        "otherData": { "time_unit": "1 cycle per microsecond" },
+    })
+}
+
+// Ends a flow at the slice that encloses cycle on the receiving track
+fn flow_end(flow: u64, cycle: Cycle) -> Value {
+    json!({
+        "name": "hop",
+        "cat": "flow",
+        "ph": "f",
+        "bp": "e",
+        "id": flow,
+        "ts": cycle,
+    })
+}
@@ -0,0 +1,4 @@
+pub mod chrome;
+pub mod topology;
+pub mod trace;
+pub mod vcd;
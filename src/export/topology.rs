@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::topology::{LinkParams, NodeId};
+use crate::comm::transfer::Direction;
+use crate::sim::monitor::LinkCounters;
+use crate::sim::network::Network;
+
+// Pixels between neighbouring nodes of the SVG and around the drawing
+const SPACING: f64 = 90.0;
+const MARGIN: f64 = 50.0;
+const RADIUS: f64 = 16.0;
+// Links in opposite directions between the same nodes are drawn this far apart
+const LINK_OFFSET: f64 = 4.0;
+
+// Everything the exports say about one outgoing link
+struct LinkInfo {
+    src: NodeId,
+    dest: NodeId,
+    dir: Direction,
+    params: LinkParams,
+    // Flits per virtual channel in the link buffer at the far end, then channels
+    buffer: usize,
+    vcs: usize,
+    // Share of cycles it was busy, None until something ran
+    utilization: Option<f64>,
+}
+
+impl LinkInfo {
+    fn label(&self) -> String {
+        let mut label = format!("bw {}, buf {}", self.params.bandwidth, self.buffer);
+        if self.vcs > 1 {
+            label += &format!("x{}", self.vcs);
+        }
+        if let Some(utilization) = self.utilization {
+            label += &format!(", util {:.0}%", utilization * 100.0);
+        }
+        label
+    }
+}
+
+fn links(network: &Network) -> Vec<LinkInfo> {
+    let counters: HashMap<(Coord, Direction), LinkCounters> = network
+        .link_usage()
+        .into_iter()
+        .map(|usage| ((usage.from, usage.dir), usage.counters))
+        .collect();
+    // The last cycle processed counts too
+    let cycles = network.cycle() + 1;
+    let ran = network.cycle() > 0 || counters.values().any(|counters| counters.flits > 0);
+
+    network
+        .nodes()
+        .iter()
+        .enumerate()
+        .flat_map(|(src, node)| {
+            node.out_links
+                .iter()
+                .enumerate()
+                .filter_map(move |(port, link)| Some((src, port, (*link)?)))
+        })
+        .map(|(src, port, link)| {
+            let node = network.node(src);
+            let dir = node.ports[port];
+            let busy = counters
+                .get(&(node.pos(), dir))
+                .map_or(0, |counters| counters.busy_cycles);
+            LinkInfo {
+                src,
+                dest: link.dest,
+                dir,
+                params: link.params,
+                buffer: network.node(link.dest).link_buffer,
+                vcs: network.node(link.dest).vc_count(),
+                utilization: ran.then(|| busy as f64 / cycles as f64),
+            }
+        })
+        .collect()
+}
+
+// Column and row of a node in the drawings, the layers of a stacked mesh side by side
+fn place(pos: Coord, layer_width: Dim) -> (f64, f64) {
+    let column = pos.x as f64 + pos.z as f64 * (layer_width as f64 + 1.0);
+    (column, pos.y as f64)
+}
+
+fn layer_width(network: &Network) -> Dim {
+    network
+        .nodes()
+        .iter()
+        .map(|node| node.x + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+// Writes the nodes and links of a network as a Graphviz digraph
+// - Nodes are pinned where they sit in the topology, the graph picks the neato layout so a mesh
+// keeps looking like one whichever Graphviz tool draws it
+// - Every link is an edge labelled with its bandwidth in flits per cycle and the link buffer at
+// its far end in flits per virtual channel, plus its utilization once the network has run
+// - The same numbers are repeated as attributes of their own for tools reading the file
+pub fn write_dot(network: &Network, mut out: impl Write) -> io::Result<()> {
+    let layer_width = layer_width(network);
+
+    writeln!(out, "digraph topology {{")?;
+    writeln!(out, "    layout=neato;")?;
+    writeln!(out, "    node [shape=circle, fontsize=10];")?;
+    writeln!(out, "    edge [fontsize=8];")?;
+    for (id, node) in network.nodes().iter().enumerate() {
+        let (x, y) = place(node.pos(), layer_width);
+        // Graphviz counts y upwards and positions in inches, subtracted so row 0 isn't -0
+        writeln!(
+            out,
+            "    n{id} [label=\"{}\", pos=\"{},{}!\"];",
+            node.pos(),
+            x * 1.5,
+            0.0 - y * 1.5
+        )?;
+    }
+    for link in links(network) {
+        let mut attrs = format!(
+            "label=\"{}\", port=\"{:?}\", bandwidth={}, latency={}, buffer={}, vcs={}",
+            link.label(),
+            link.dir,
+            link.params.bandwidth,
+            link.params.latency,
+            link.buffer,
+            link.vcs
+        );
+        if let Some(utilization) = link.utilization {
+            attrs += &format!(
+                ", utilization={utilization:.4}, penwidth={:.2}, color=\"{}\"",
+                1.0 + 4.0 * utilization,
+                heat(utilization)
+            );
+        }
+        writeln!(out, "    n{} -> n{} [{attrs}];", link.src, link.dest)?;
+    }
+    writeln!(out, "}}")
+}
+
+// Draws the same picture as write_dot straight to SVG, without Graphviz
+// - Links are arrows, shaded green to red by utilization once the network has run and grey
+// before, hovering one shows its label
+// - Wrap links of a torus are drawn straight across the mesh
+pub fn write_svg(network: &Network, mut out: impl Write) -> io::Result<()> {
+    let layer_width = layer_width(network);
+    let at = |id: NodeId| {
+        let (x, y) = place(network.node(id).pos(), layer_width);
+        (MARGIN + x * SPACING, MARGIN + y * SPACING)
+    };
+    let (width, height) = (0..network.nodes().len())
+        .map(at)
+        .fold((0.0, 0.0), |(w, h): (f64, f64), (x, y)| {
+            (w.max(x), h.max(y))
+        });
+
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"monospace\">",
+        width + MARGIN,
+        height + MARGIN
+    )?;
+    writeln!(
+        out,
+        "  <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M0,0 L10,5 L0,10 z\" fill=\"context-stroke\"/></marker></defs>"
+    )?;
+
+    for link in links(network) {
+        let ((x1, y1), (x2, y2)) = (at(link.src), at(link.dest));
+        let length = (x2 - x1).hypot(y2 - y1);
+        if length == 0.0 {
+            continue;
+        }
+        // Shortened to the node outlines and moved to the right of the direction of travel
+        let (dx, dy) = ((x2 - x1) / length, (y2 - y1) / length);
+        let (ox, oy) = (-dy * LINK_OFFSET, dx * LINK_OFFSET);
+        let (color, width) = match link.utilization {
+            Some(utilization) => (heat(utilization), 1.0 + 4.0 * utilization),
+            None => ("#999999".to_string(), 1.0),
+        };
+        writeln!(
+            out,
+            "  <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{color}\" stroke-width=\"{width:.2}\" marker-end=\"url(#arrow)\"><title>{} {:?}: {}</title></line>",
+            x1 + dx * RADIUS + ox,
+            y1 + dy * RADIUS + oy,
+            x2 - dx * RADIUS + ox,
+            y2 - dy * RADIUS + oy,
+            network.node(link.src).pos(),
+            link.dir,
+            link.label()
+        )?;
+    }
+
+    for (id, node) in network.nodes().iter().enumerate() {
+        let (x, y) = at(id);
+        let pos = node.pos();
+        writeln!(
+            out,
+            "  <g><title>node {pos}</title><circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"{RADIUS}\" fill=\"white\" stroke=\"black\"/><text x=\"{x:.1}\" y=\"{:.1}\" font-size=\"9\" text-anchor=\"middle\">{},{},{}</text></g>",
+            y + 3.0,
+            pos.x,
+            pos.y,
+            pos.z
+        )?;
+    }
+
+    writeln!(out, "</svg>")
+}
+
+// Green through yellow to red
+fn heat(utilization: f64) -> String {
+    let utilization = utilization.clamp(0.0, 1.0);
+    let red = (utilization * 2.0).min(1.0) * 255.0;
+    let green = ((1.0 - utilization) * 2.0).min(1.0) * 200.0;
+    format!("#{:02x}{:02x}00", red as u8, green as u8)
+}
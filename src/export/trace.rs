@@ -0,0 +1,292 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::arch::coord::{Coord, Dim};
+use crate::comm::packet::Event;
+use crate::comm::transfer::Direction;
+
+const CSV_HEADER: &str =
+    "cycle,event,id,x,y,z,dir,vc,src_x,src_y,src_z,dest_x,dest_y,dest_z,created,hops,flits,class";
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error("Unable to access the trace: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid trace on line {line}: {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum TraceFormat {
+    // One JSON object per line, tagged with the event name
+    #[default]
+    JsonLines,
+    // One row per event, columns an event doesn't have are left empty
+    // - x, y and z are where it happened, dir the port it went out or came in through
+    Csv,
+}
+
+impl TraceFormat {
+    // By extension, .csv is CSV and anything else JSON Lines
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => TraceFormat::Csv,
+            _ => TraceFormat::JsonLines,
+        }
+    }
+}
+
+// Events a trace keeps, the per hop and per packet ones every analysis starts from
+pub fn is_traced(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::PacketSent { .. } | Event::PacketReceived { .. } | Event::PacketArrived { .. }
+    )
+}
+
+// Writes the sent, received and arrived events of a run, anything else is skipped
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    header_written: bool,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    // Format picked from the extension of path
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, TraceError> {
+        let path = path.as_ref();
+        let file = File::create(path)?;
+        Ok(Self::new(
+            BufWriter::new(file),
+            TraceFormat::from_path(path),
+        ))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        Self {
+            out,
+            format,
+            header_written: false,
+        }
+    }
+
+    pub fn write(&mut self, event: &Event) -> Result<(), TraceError> {
+        if !is_traced(event) {
+            return Ok(());
+        }
+
+        match self.format {
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut self.out, event).map_err(io::Error::from)?;
+                writeln!(self.out)?;
+            }
+            TraceFormat::Csv => {
+                if !self.header_written {
+                    writeln!(self.out, "{CSV_HEADER}")?;
+                    self.header_written = true;
+                }
+                writeln!(self.out, "{}", csv_row(event))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes every event already waiting on the receiver, returns how many there were
+    pub fn write_all(
+        &mut self,
+        event_rx: &mut UnboundedReceiver<Event>,
+    ) -> Result<usize, TraceError> {
+        let mut count = 0;
+        while let Ok(event) = event_rx.try_recv() {
+            self.write(&event)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    // Flushes and hands the output back
+    pub fn finish(mut self) -> Result<W, TraceError> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Loads a trace written by TraceWriter::create, the format is picked the same way
+pub fn open_trace(path: impl AsRef<Path>) -> Result<Vec<Event>, TraceError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    read_trace(BufReader::new(file), TraceFormat::from_path(path))
+}
+
+pub fn read_trace(input: impl BufRead, format: TraceFormat) -> Result<Vec<Event>, TraceError> {
+    let mut events = Vec::new();
+    let mut started = false;
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let number = index + 1;
+        // A byte order mark can only come before the first line with anything on it
+        let line = match started {
+            true => line.as_str(),
+            false => line.trim_start_matches('\u{feff}'),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let first = !started;
+        started = true;
+
+        let event = match format {
+            TraceFormat::JsonLines => serde_json::from_str(line).map_err(|err| err.to_string()),
+            TraceFormat::Csv if first => {
+                if line.trim() != CSV_HEADER {
+                    return Err(TraceError::Parse {
+                        line: number,
+                        reason: format!("expected the header {CSV_HEADER}"),
+                    });
+                }
+                continue;
+            }
+            TraceFormat::Csv => parse_csv_row(line),
+        };
+
+        events.push(event.map_err(|reason| TraceError::Parse {
+            line: number,
+            reason,
+        })?);
+    }
+
+    Ok(events)
+}
+
+fn csv_row(event: &Event) -> String {
+    let coord = |pos: &Coord| format!("{},{},{}", pos.x, pos.y, pos.z);
+    let none = ",,";
+
+    match event {
+        Event::PacketSent {
+            id,
+            send_dir,
+            vc,
+            from,
+            cycle,
+        } => format!(
+            "{cycle},PacketSent,{id},{},{send_dir:?},{vc},{none},{none},,,,",
+            coord(from)
+        ),
+        Event::PacketReceived {
+            id,
+            recv_dir,
+            at,
+            cycle,
+        } => format!(
+            "{cycle},PacketReceived,{id},{},{recv_dir:?},,{none},{none},,,,",
+            coord(at)
+        ),
+        Event::PacketArrived {
+            id,
+            at,
+            src,
+            dest,
+            created,
+            hops,
+            flits,
+            class,
+            cycle,
+        } => format!(
+            "{cycle},PacketArrived,{id},{},,,{},{},{created},{hops},{flits},{class}",
+            coord(at),
+            coord(src),
+            coord(dest)
+        ),
+        _ => unreachable!("Only traced events are written"),
+    }
+}
+
+fn parse_csv_row(line: &str) -> Result<Event, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() != CSV_HEADER.split(',').count() {
+        return Err(format!(
+            "expected {} columns",
+            CSV_HEADER.split(',').count()
+        ));
+    }
+
+    let number = |column: usize| -> Result<u64, String> {
+        fields[column].parse().map_err(|_| {
+            format!(
+                "{:?} in column {} isn't a number",
+                fields[column],
+                column + 1
+            )
+        })
+    };
+    let dim = |column: usize| -> Result<Dim, String> {
+        Dim::try_from(number(column)?).map_err(|_| format!("column {} is out of range", column + 1))
+    };
+    let coord = |column: usize| -> Result<Coord, String> {
+        Ok(Coord::new(dim(column)?, dim(column + 1)?, dim(column + 2)?))
+    };
+    let cycle = number(0)?;
+    let id = number(2)? as usize;
+
+    match fields[1] {
+        "PacketSent" => Ok(Event::PacketSent {
+            id,
+            send_dir: parse_direction(fields[6])?,
+            vc: number(7)? as usize,
+            from: coord(3)?,
+            cycle,
+        }),
+        "PacketReceived" => Ok(Event::PacketReceived {
+            id,
+            recv_dir: parse_direction(fields[6])?,
+            at: coord(3)?,
+            cycle,
+        }),
+        "PacketArrived" => Ok(Event::PacketArrived {
+            id,
+            at: coord(3)?,
+            src: coord(8)?,
+            dest: coord(11)?,
+            created: number(14)?,
+            hops: number(15)? as usize,
+            flits: number(16)? as usize,
+            class: number(17)? as usize,
+            cycle,
+        }),
+        other => Err(format!("unknown event {other:?}")),
+    }
+}
+
+// Inverse of the Debug output of a direction
+fn parse_direction(name: &str) -> Result<Direction, String> {
+    let dir = match name {
+        "Up" => Direction::Up,
+        "Down" => Direction::Down,
+        "Left" => Direction::Left,
+        "Right" => Direction::Right,
+        "Above" => Direction::Above,
+        "Below" => Direction::Below,
+        "Init" => Direction::Init,
+        _ => {
+            let port = name
+                .strip_prefix("Port(")
+                .and_then(|rest| rest.strip_suffix(')'))
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(|| format!("unknown direction {name:?}"))?;
+            Direction::Port(port)
+        }
+    };
+
+    Ok(dir)
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::arch::coord::Coord;
+use crate::comm::transfer::Direction;
+use crate::sim::monitor::TimeSeries;
+
+// Widths of the vector signals, ids and flit counts past them are cut to the low bits
+const PACKET_BITS: usize = 32;
+const OCCUPANCY_BITS: usize = 16;
+
+// Printable characters VCD identifiers are made of
+const CODE_FIRST: u8 = b'!';
+const CODE_CHARS: usize = (b'~' - b'!' + 1) as usize;
+
+// One variable of the dump and the value it was last given
+struct Signal {
+    code: String,
+    bits: usize,
+    value: Option<String>,
+}
+
+impl Signal {
+    fn new(index: usize, bits: usize) -> Self {
+        // Base 94 over the printable characters
+        let mut code = String::new();
+        let mut rest = index;
+        loop {
+            code.push((CODE_FIRST + (rest % CODE_CHARS) as u8) as char);
+            rest /= CODE_CHARS;
+            if rest == 0 {
+                break;
+            }
+            rest -= 1;
+        }
+
+        Self {
+            code,
+            bits,
+            value: None,
+        }
+    }
+
+    // None is undefined
+    fn set(&mut self, value: Option<u64>, mut out: impl Write) -> io::Result<()> {
+        let value = match (value, self.bits) {
+            (Some(value), 1) => format!("{}", value & 1),
+            (None, 1) => "x".to_string(),
+            (Some(value), bits) => format!("b{:b} ", value & ((1 << bits) - 1)),
+            (None, _) => "bx ".to_string(),
+        };
+        if self.value.as_ref() != Some(&value) {
+            writeln!(out, "{value}{}", self.code)?;
+            self.value = Some(value);
+        }
+
+        Ok(())
+    }
+}
+
+// Writes what a grid sampled as a Value Change Dump for waveform viewers
+// - Needs sampling turned on with Grid::set_sample_interval, links are only followed while it is
+// - Every node is a scope holding a valid and a packet signal per outgoing link and an occupancy
+// signal per buffer, next to a clk at the top
+// - A cycle is one clock period of 1ns, clk rises at its start and everything changes with it
+// - packet is undefined whenever valid is low, with several flits on a link in the same cycle it
+// holds the last one
+// - Buffers hold their value between samples, sample every cycle for an exact waveform
+pub fn write_vcd(series: &TimeSeries, mut out: impl Write) -> io::Result<()> {
+    let mut clock = Signal::new(0, 1);
+    let mut signals = 1;
+    let mut signal = |bits| {
+        signals += 1;
+        Signal::new(signals - 1, bits)
+    };
+    let mut valid: Vec<Signal> = series.links.iter().map(|_| signal(1)).collect();
+    let mut packet: Vec<Signal> = series.links.iter().map(|_| signal(PACKET_BITS)).collect();
+    let mut occupancy: Vec<Signal> = series
+        .buffers
+        .iter()
+        .map(|_| signal(OCCUPANCY_BITS))
+        .collect();
+
+    // Declarations grouped by node, in the order the nodes were built
+    let mut nodes: Vec<Coord> = Vec::new();
+    let mut declarations: HashMap<Coord, Vec<String>> = HashMap::new();
+    let mut declare = |at: Coord, line: String| {
+        declarations
+            .entry(at)
+            .or_insert_with(|| {
+                nodes.push(at);
+                Vec::new()
+            })
+            .push(line);
+    };
+    for (index, &(at, dir)) in series.links.iter().enumerate() {
+        let name = signal_name(dir);
+        declare(
+            at,
+            format!("$var wire 1 {} {name}_valid $end", valid[index].code),
+        );
+        declare(
+            at,
+            format!(
+                "$var wire {PACKET_BITS} {} {name}_packet $end",
+                packet[index].code
+            ),
+        );
+    }
+    for (index, buffer) in series.buffers.iter().enumerate() {
+        let name = match buffer.dir {
+            Direction::Init => String::new(),
+            dir => signal_name(dir) + "_",
+        };
+        declare(
+            buffer.at,
+            format!(
+                "$var wire {OCCUPANCY_BITS} {} {name}{} $end",
+                occupancy[index].code,
+                buffer.kind.name()
+            ),
+        );
+    }
+
+    writeln!(out, "$version mesh simulator $end")?;
+    writeln!(out, "$timescale 500ps $end")?;
+    writeln!(out, "$scope module mesh $end")?;
+    writeln!(out, "$var wire 1 {} clk $end", clock.code)?;
+    for at in &nodes {
+        writeln!(out, "$scope module node_{}_{}_{} $end", at.x, at.y, at.z)?;
+        for line in &declarations[at] {
+            writeln!(out, "{line}")?;
+        }
+        writeln!(out, "$upscope $end")?;
+    }
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$enddefinitions $end")?;
+
+    let first = series.samples.first().map(|sample| sample.cycle);
+    let first_sent = series.activity.first().map(|activity| activity.cycle);
+    let Some(start) = first.into_iter().chain(first_sent).min() else {
+        return Ok(());
+    };
+    let last = series.samples.last().map(|sample| sample.cycle);
+    let last_sent = series.activity.last().map(|activity| activity.cycle);
+    // One more cycle so the last flits are seen going low again
+    let end = last.max(last_sent).unwrap_or(start) + 1;
+
+    let mut samples = series.samples.iter().peekable();
+    let mut activity = series.activity.iter().peekable();
+    let mut sent: Vec<Option<u64>> = vec![None; series.links.len()];
+    let mut buffered: Vec<u64> = vec![0; series.buffers.len()];
+
+    for cycle in start..=end {
+        sent.fill(None);
+        while let Some(flit) = activity.next_if(|flit| flit.cycle <= cycle) {
+            sent[flit.link] = Some(flit.id as u64);
+        }
+        while let Some(sample) = samples.next_if(|sample| sample.cycle <= cycle) {
+            for (flits, &now) in buffered.iter_mut().zip(&sample.occupancy) {
+                *flits = now as u64;
+            }
+        }
+
+        writeln!(out, "#{}", cycle * 2)?;
+        if cycle == start {
+            writeln!(out, "$dumpvars")?;
+        }
+        clock.set(Some(1), &mut out)?;
+        for (link, &id) in sent.iter().enumerate() {
+            valid[link].set(Some(id.is_some() as u64), &mut out)?;
+            packet[link].set(id, &mut out)?;
+        }
+        for (buffer, &flits) in occupancy.iter_mut().zip(&buffered) {
+            buffer.set(Some(flits), &mut out)?;
+        }
+        if cycle == start {
+            writeln!(out, "$end")?;
+        }
+
+        writeln!(out, "#{}", cycle * 2 + 1)?;
+        clock.set(Some(0), &mut out)?;
+    }
+
+    Ok(())
+}
+
+// Lower case direction usable as a signal name, Port(3) becomes port3
+fn signal_name(dir: Direction) -> String {
+    format!("{dir:?}")
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_lowercase()
+}
@@ -0,0 +1,54 @@
+// Cycle accurate simulator of networks on chip
+// - Build a Grid (or a Network over any Topology), send it packets and run it, every hop is
+// reported as an Event on the receiver the grid hands out when it is built
+// - Stats turns the events into latency and throughput figures, the exports and visualizations
+// write them out for other tools or draw them
+// - The modules are private, everything supported is re-exported here and the engine, router and
+// transfer internals stay free to change
+mod arch;
+mod comm;
+mod export;
+mod sim;
+mod viz;
+
+// Topologies and the routers of their nodes
+pub use arch::builder::GridBuilder;
+pub use arch::coord::{Coord, Dim};
+pub use arch::grid::{Grid, GridAccessError, TopologyKind};
+pub use arch::node::{MeshNode, NodeParams};
+pub use arch::router::{PipelineStage, RouterPipeline, StageTiming, VcSelection, VirtualChannels};
+pub use arch::routing::{NegativeFirst, NorthLast, OddEven, RoutingAlgorithm, WestFirst};
+pub use arch::topology::{Graph, Link, LinkParams, NodeId, PortId, Topology};
+
+// What travels through the network and what it reports
+pub use comm::packet::{Event, MetaData, Packet, PacketData};
+pub use comm::transfer::{Direction, NodeCommError, SendDirError};
+
+// Running, configuring and measuring
+pub use sim::config::{
+    Config, ConfigError, ConfigFormat, NodeOverride, NodeRate, NodesConfig, RouterConfig,
+    RoutingKind, TopologyConfig, TrafficConfig,
+};
+pub use sim::deadlock::{Deadlock, DeadlockedChannel};
+pub use sim::engine::Cycle;
+pub use sim::monitor::{
+    BufferId, BufferKind, LinkActivity, LinkCounters, LinkUsage, Sample, TimeSeries,
+};
+pub use sim::network::Network;
+pub use sim::stats::{Distribution, PairStats, Stats};
+pub use sim::sweep::{Sweep, SweepCurve, SweepPoint};
+pub use sim::traffic::{InjectionProcess, TrafficError, TrafficGenerator, TrafficPattern};
+
+// Exports for other tools
+pub use export::chrome::{chrome_trace, write_chrome_trace};
+pub use export::topology::{write_dot, write_svg};
+pub use export::trace::{TraceError, TraceFormat, TraceWriter, open_trace, read_trace};
+pub use export::vcd::write_vcd;
+
+// Visualizations
+pub use viz::heatmap::{Heatmap, Metric, RenderError};
+#[cfg(feature = "tui")]
+pub use viz::tui::{Control, Dashboard, InFlight, NodeCounters};
+
+#[cfg(test)]
+mod tests;
@@ -1,98 +1,97 @@
-mod arch;
-mod comm;
-use crate::{
-    arch::grid::{Grid, GridAccessError},
-    comm::packet::{Event, Packet, PacketData},
-};
+use std::io;
+use std::process;
 
-async fn send_packet(grid: &Grid, packet: Packet) {
-    if let Ok(src_node) = grid.access_node(packet.header.cur_pos) {
-        Grid::send_packet_grid(src_node, packet)
-            .await
-            .expect("Failed to send packet");
-    }
-}
-
-#[tokio::main]
-async fn main() {}
+use clap::Parser;
 
-#[tokio::test]
-async fn small_packet_load() -> Result<(), GridAccessError> {
-    let mut grid: Grid = Grid::default();
-    let mut event_rx = grid.init_grid(5, 5)?;
+use crate::cli::{Cli, CliError};
+#[cfg(test)]
+use mesh_sim::{Config, ConfigFormat};
 
-    let (src_1, dest_1) = ((4, 3), (1, 0));
-    let (src_2, dest_2) = ((0, 0), (4, 4));
-    let (src_3, dest_3) = ((1, 3), (4, 0));
-    let (src_4, dest_4) = ((0, 0), (1, 1));
-    let packet1 = Packet::new(PacketData::Integer(0), src_1, dest_1);
-    let packet2 = Packet::new(PacketData::Integer(0), src_2, dest_2);
-    let packet3 = Packet::new(PacketData::Integer(0), src_3, dest_3);
-    let packet4 = Packet::new(PacketData::Integer(0), src_4, dest_4);
+mod cli;
 
-    let mut expected_arrived = 4;
-    let test_result = tokio::time::timeout(std::time::Duration::from_secs(1), async {
-        send_packet(&grid, packet1).await;
-        send_packet(&grid, packet2).await;
-        send_packet(&grid, packet3).await;
-        send_packet(&grid, packet4).await;
-
-        while expected_arrived > 0 {
-            if let Some(event) = event_rx.recv().await {
-                match event {
-                    // Only care about when packets arrive at their final destination
-                    Event::PacketArrived { id, at, dest } => {
-                        assert_eq!(
-                            at, dest,
-                            "Packet id {id} arrived at {:?} but should've arrived at {:?}",
-                            at, dest
-                        );
-                        expected_arrived -= 1;
-                    }
-                    _ => {}
-                }
-            }
+fn main() {
+    let cli = Cli::parse();
+    match cli::execute(cli, &mut io::stdout().lock()) {
+        // Output piped into something that stopped reading, like head
+        Err(CliError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(err) => {
+            eprintln!("mesh-sim: {err}");
+            process::exit(1);
         }
-    })
-    .await;
-
-    assert!(test_result.is_ok(), "Packet test timed out!");
-    Ok(())
+        Ok(()) => {}
+    }
 }
 
-#[tokio::test]
-async fn same_path_load() -> Result<(), GridAccessError> {
-    let mut grid: Grid = Grid::default();
-    let mut event_rx = grid.init_grid(5, 5)?;
+#[test]
+fn command_line() -> Result<(), CliError> {
+    let dir = std::env::temp_dir().join(format!("mesh-sim-cli-{}", process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let config = dir.join("mesh.toml");
+    let trace = dir.join("trace.jsonl");
+    std::fs::write(
+        &config,
+        "[topology]\nwidth = 3\nheight = 3\n\n[traffic]\nrate = 0.2\ncycles = 50\npacket_flits = 3\n",
+    )?;
+    let path = |path: &std::path::Path| path.to_str().unwrap().to_string();
+    let execute = |args: &[&str]| -> Result<String, CliError> {
+        let cli = Cli::try_parse_from(["mesh-sim"].iter().chain(args)).unwrap();
+        let mut out = Vec::new();
+        cli::execute(cli, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    };
 
-    let (src, dest) = ((0, 0), (4, 4));
-    let mut expected_arrived = 10000;
+    let (config, trace) = (path(&config), path(&trace));
+    let run = execute(&[
+        "run", &config, "--seed", "5", "--format", "json", "--trace", &trace,
+    ])?;
+    let summary: serde_json::Value = serde_json::from_str(&run).unwrap();
+    assert!(summary["arrived"].as_u64().unwrap() > 0);
+    assert_eq!(summary["in_network"], 0);
 
-    let test_result = tokio::time::timeout(std::time::Duration::from_secs(20), async {
-        for _ in 0..expected_arrived {
-            let packet = Packet::new(PacketData::Integer(0), src, dest);
-            send_packet(&grid, packet).await;
-        }
+    // The trace gives the same summary back, and so does running its packets again with the
+    // same lengths
+    let replay = execute(&["replay", &trace, "--format", "json"])?;
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&replay).unwrap(),
+        summary
+    );
+    let rerun = execute(&["replay", &trace, "--config", &config, "--format", "json"])?;
+    let rerun: serde_json::Value = serde_json::from_str(&rerun).unwrap();
+    assert_eq!(rerun, summary);
 
-        while expected_arrived > 0 {
-            if let Some(event) = event_rx.recv().await {
-                match event {
-                    // Only care about when packets arrive at their final destination
-                    Event::PacketArrived { id, at, dest } => {
-                        assert_eq!(
-                            at, dest,
-                            "Packet id {id} arrived at {:?} but should've arrived at {:?}",
-                            at, dest
-                        );
-                        expected_arrived -= 1;
-                    }
-                    _ => {}
-                }
-            }
-        }
-    })
-    .await;
+    // Every default filled in still parses to the same config
+    let toml = execute(&["inspect", &config, "--format", "toml"])?;
+    assert_eq!(
+        Config::parse(&toml, ConfigFormat::Toml)?,
+        Config::load(&config)?
+    );
+    assert!(execute(&["inspect", &config])?.starts_with("Mesh of 3x3x1, 9 nodes and 24 links"));
+
+    let sweep = execute(&[
+        "sweep",
+        &config,
+        "--rates",
+        "0.05,0.1",
+        "--warmup",
+        "50",
+        "--measure",
+        "100",
+        "--drain",
+        "200",
+    ])?;
+    assert_eq!(sweep.lines().count(), 4);
+    // Rates are checked before anything runs
+    for rates in [
+        &["--rates", "0.1,-0.2"][..],
+        &["--rates", "NaN"],
+        &["--rates", "0.2,0.1"],
+        &["--step", "0"],
+        &["--from", "0.5", "--to", "2"],
+    ] {
+        let args = [&["sweep", config.as_str()][..], rates].concat();
+        assert!(matches!(execute(&args), Err(CliError::Usage(_))));
+    }
 
-    assert!(test_result.is_ok(), "Packet test timed out!");
+    std::fs::remove_dir_all(&dir)?;
     Ok(())
 }
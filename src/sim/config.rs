@@ -0,0 +1,480 @@
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::{GridAccessError, TopologyKind};
+use crate::arch::node::NodeParams;
+use crate::arch::router::{RouterPipeline, VcSelection, VirtualChannels};
+use crate::arch::topology::LinkParams;
+use crate::sim::engine::Cycle;
+use crate::sim::traffic::{InjectionProcess, TrafficGenerator, TrafficPattern};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Unable to read the config: {0}")]
+    Io(#[from] io::Error),
+
+    // Syntax errors and values of the wrong type, field is the path to the value
+    #[error("Unable to parse the config at {field}: {reason}")]
+    Parse { field: String, reason: String },
+
+    // Values that parse but can't be simulated
+    #[error("Invalid config at {field}: {reason}")]
+    Invalid { field: String, reason: String },
+
+    #[error("{0}")]
+    Grid(#[from] GridAccessError),
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    // By extension, .json is JSON and anything else TOML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+// Everything needed to build a grid and drive it, see Grid::from_config
+// - Only the topology section and its width and height are required, anything else left out
+// keeps the default the grid setters start from
+// - Unknown fields are rejected so a typo can't silently fall back to a default
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub topology: TopologyConfig,
+    // Links inside a layer
+    #[serde(default)]
+    pub links: LinkParams,
+    // Links between the layers of a stacked mesh
+    #[serde(default)]
+    pub vertical_links: LinkParams,
+    #[serde(default)]
+    pub nodes: NodesConfig,
+    #[serde(default)]
+    pub router: RouterConfig,
+    // None keeps negative first, only a 2D mesh routes hop by hop so nothing else can set it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<RoutingKind>,
+    pub traffic: Option<TrafficConfig>,
+}
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TopologyConfig {
+    #[serde(default)]
+    pub kind: TopologyKind,
+    pub width: Dim,
+    pub height: Dim,
+    #[serde(default = "one")]
+    pub depth: Dim,
+}
+
+fn one() -> Dim {
+    1
+}
+
+// Parameters of every node, then the nodes that differ
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodesConfig {
+    pub tx_rate: u64,
+    pub rx_rate: u64,
+    pub link_buffer: usize,
+    pub inner_buffer: usize,
+    pub overrides: Vec<NodeOverride>,
+}
+
+impl Default for NodesConfig {
+    fn default() -> Self {
+        let params = NodeParams::default();
+        Self {
+            tx_rate: params.tx_rate,
+            rx_rate: params.rx_rate,
+            link_buffer: params.link_buffer,
+            inner_buffer: params.inner_buffer,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl NodesConfig {
+    pub fn params(&self) -> NodeParams {
+        NodeParams {
+            tx_rate: self.tx_rate,
+            rx_rate: self.rx_rate,
+            link_buffer: self.link_buffer,
+            inner_buffer: self.inner_buffer,
+        }
+    }
+}
+
+// A node whose parameters differ from the rest, fields left out are taken from the nodes section
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NodeOverride {
+    pub at: Coord,
+    pub tx_rate: Option<u64>,
+    pub rx_rate: Option<u64>,
+    pub link_buffer: Option<usize>,
+    pub inner_buffer: Option<usize>,
+}
+
+impl NodeOverride {
+    pub fn params(&self, defaults: NodeParams) -> NodeParams {
+        NodeParams {
+            tx_rate: self.tx_rate.unwrap_or(defaults.tx_rate),
+            rx_rate: self.rx_rate.unwrap_or(defaults.rx_rate),
+            link_buffer: self.link_buffer.unwrap_or(defaults.link_buffer),
+            inner_buffer: self.inner_buffer.unwrap_or(defaults.inner_buffer),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouterConfig {
+    // None keeps the model where a packet crosses a node within the cycle it is received
+    pub pipeline: Option<RouterPipeline>,
+    pub virtual_channels: usize,
+    pub vc_selection: VcSelection,
+    // Cycles between deadlock checks, None only checks once the network stalls
+    pub deadlock_check: Option<Cycle>,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        let vcs = VirtualChannels::default();
+        Self {
+            pipeline: None,
+            virtual_channels: vcs.count,
+            vc_selection: vcs.selection,
+            deadlock_check: None,
+        }
+    }
+}
+
+impl RouterConfig {
+    pub fn virtual_channels(&self) -> VirtualChannels {
+        VirtualChannels {
+            count: self.virtual_channels,
+            selection: self.vc_selection,
+        }
+    }
+}
+
+// Hop by hop routing algorithms by name, only used by 2D meshes, see Grid::set_routing
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingKind {
+    #[default]
+    NegativeFirst,
+    WestFirst,
+    NorthLast,
+    OddEven,
+}
+
+// Synthetic workload injected from cycle 0 on, see TrafficGenerator
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrafficConfig {
+    pub pattern: TrafficPattern,
+    pub process: InjectionProcess,
+    // Packets per node per cycle
+    pub rate: f64,
+    pub packet_flits: usize,
+    pub seed: u64,
+    // Cycles during which packets are injected
+    pub cycles: Cycle,
+    // Nodes injecting at a rate of their own
+    pub node_rates: Vec<NodeRate>,
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        Self {
+            pattern: TrafficPattern::default(),
+            process: InjectionProcess::default(),
+            rate: 0.1,
+            packet_flits: 1,
+            seed: 0,
+            cycles: 1000,
+            node_rates: Vec::new(),
+        }
+    }
+}
+
+impl TrafficConfig {
+    pub fn generator(&self) -> TrafficGenerator {
+        let mut generator =
+            TrafficGenerator::new(self.pattern.clone(), self.process, self.rate, self.seed);
+        generator.set_packet_flits(self.packet_flits);
+        for node in &self.node_rates {
+            generator.set_node_rate(node.at, node.rate);
+        }
+        generator
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NodeRate {
+    pub at: Coord,
+    pub rate: f64,
+}
+
+impl Config {
+    // Format picked from the extension of path
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?, ConfigFormat::from_path(path))
+    }
+
+    // Parses and validates
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        let config: Self = match format {
+            ConfigFormat::Toml => serde_path_to_error::deserialize(toml::Deserializer::new(text))
+                .map_err(parse_error)?,
+            ConfigFormat::Json => {
+                let mut json = serde_json::Deserializer::from_str(text);
+                let config = serde_path_to_error::deserialize(&mut json).map_err(parse_error)?;
+                json.end().map_err(|err| ConfigError::Parse {
+                    field: "the end".to_string(),
+                    reason: err.to_string(),
+                })?;
+                config
+            }
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Checks every value that would make the grid misbehave or panic, stops at the first one
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let topology = &self.topology;
+        check(topology.width > 0, "topology.width", "must be at least 1")?;
+        check(topology.height > 0, "topology.height", "must be at least 1")?;
+        check(topology.depth > 0, "topology.depth", "must be at least 1")?;
+        let inside =
+            |at: Coord| at.x < topology.width && at.y < topology.height && at.z < topology.depth;
+
+        check_link(&self.links, "links")?;
+        check_link(&self.vertical_links, "vertical_links")?;
+
+        check_node(self.nodes.params(), "nodes")?;
+        for (index, node) in self.nodes.overrides.iter().enumerate() {
+            let field = format!("nodes.overrides[{index}]");
+            check(
+                inside(node.at),
+                format!("{field}.at"),
+                "is outside the grid",
+            )?;
+            check_node(node.params(self.nodes.params()), &field)?;
+        }
+
+        check(
+            self.routing.is_none() || (topology.kind == TopologyKind::Mesh && topology.depth == 1),
+            "routing",
+            "only applies to a 2D mesh, a torus or stacked mesh uses source routes",
+        )?;
+
+        let router = &self.router;
+        check_vcs(
+            router.virtual_channels(),
+            "router.virtual_channels",
+            "router.vc_selection",
+        )?;
+        check(
+            router.deadlock_check != Some(0),
+            "router.deadlock_check",
+            "must be at least 1 cycle",
+        )?;
+
+        let Some(traffic) = &self.traffic else {
+            return Ok(());
+        };
+        check_rate(traffic.rate, "traffic.rate")?;
+        if let Err(err) = traffic
+            .pattern
+            .check((topology.width, topology.height, topology.depth))
+        {
+            return Err(ConfigError::Invalid {
+                field: "traffic.pattern".to_string(),
+                reason: err.to_string(),
+            });
+        }
+        // A bernoulli process injects at most one packet a cycle
+        let bernoulli = |rate: f64, field: &str| {
+            check(
+                traffic.process != InjectionProcess::Bernoulli || rate <= 1.0,
+                field,
+                "can't be above 1 with the bernoulli process",
+            )
+        };
+        bernoulli(traffic.rate, "traffic.rate")?;
+        check(
+            traffic.packet_flits > 0,
+            "traffic.packet_flits",
+            "must be at least 1",
+        )?;
+        if let TrafficPattern::Hotspot { hotspots, fraction } = &traffic.pattern {
+            let field = "traffic.pattern.hotspot";
+            check(
+                !hotspots.is_empty(),
+                format!("{field}.hotspots"),
+                "can't be empty",
+            )?;
+            for (index, &at) in hotspots.iter().enumerate() {
+                check(
+                    inside(at),
+                    format!("{field}.hotspots[{index}]"),
+                    "is outside the grid",
+                )?;
+            }
+            check(
+                (0.0..=1.0).contains(fraction),
+                format!("{field}.fraction"),
+                "must be between 0 and 1",
+            )?;
+        }
+        if let InjectionProcess::OnOff {
+            on_cycles,
+            off_cycles,
+        } = traffic.process
+        {
+            let field = "traffic.process.on_off";
+            check(
+                on_cycles >= 1.0,
+                format!("{field}.on_cycles"),
+                "must be at least 1",
+            )?;
+            check(
+                off_cycles >= 1.0,
+                format!("{field}.off_cycles"),
+                "must be at least 1",
+            )?;
+        }
+        for (index, node) in traffic.node_rates.iter().enumerate() {
+            let field = format!("traffic.node_rates[{index}]");
+            check(
+                inside(node.at),
+                format!("{field}.at"),
+                "is outside the grid",
+            )?;
+            check_rate(node.rate, &format!("{field}.rate"))?;
+            bernoulli(node.rate, &format!("{field}.rate"))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn check(ok: bool, field: impl Into<String>, reason: &str) -> Result<(), ConfigError> {
+    match ok {
+        true => Ok(()),
+        false => Err(ConfigError::Invalid {
+            field: field.into(),
+            reason: reason.to_string(),
+        }),
+    }
+}
+
+pub(crate) fn check_link(params: &LinkParams, field: &str) -> Result<(), ConfigError> {
+    check(
+        params.latency > 0,
+        format!("{field}.latency"),
+        "must be at least 1 cycle",
+    )?;
+    check(
+        params.bandwidth > 0,
+        format!("{field}.bandwidth"),
+        "must be at least 1 flit",
+    )?;
+    check(
+        params.credit_latency > 0,
+        format!("{field}.credit_latency"),
+        "must be at least 1 cycle",
+    )
+}
+
+// Fields of the count and the selection are named separately, the config keeps them apart
+pub(crate) fn check_vcs(
+    vcs: VirtualChannels,
+    count_field: &str,
+    selection_field: &str,
+) -> Result<(), ConfigError> {
+    check(vcs.count > 0, count_field, "must be at least 1")?;
+    let groups = match vcs.selection {
+        VcSelection::Any => None,
+        VcSelection::Class { classes } => Some(("classes", classes)),
+        VcSelection::Phase { phases } => Some(("phases", phases)),
+    };
+    if let Some((name, groups)) = groups {
+        let field = format!("{selection_field}.{name}");
+        check(groups > 0, &field, "must be at least 1")?;
+        check(
+            groups <= vcs.count,
+            &field,
+            "needs at least one virtual channel per group",
+        )?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_node(params: NodeParams, field: &str) -> Result<(), ConfigError> {
+    check(
+        params.tx_rate > 0,
+        format!("{field}.tx_rate"),
+        "must be at least 1 flit",
+    )?;
+    check(
+        params.rx_rate > 0,
+        format!("{field}.rx_rate"),
+        "must be at least 1 flit",
+    )?;
+    check(
+        params.link_buffer > 0,
+        format!("{field}.link_buffer"),
+        "must be at least 1 flit",
+    )?;
+    check(
+        params.inner_buffer > 0,
+        format!("{field}.inner_buffer"),
+        "must be at least 1 flit",
+    )
+}
+
+fn check_rate(rate: f64, field: &str) -> Result<(), ConfigError> {
+    check(
+        rate.is_finite() && rate >= 0.0,
+        field,
+        "must be a rate of 0 or more",
+    )
+}
+
+fn parse_error<E: Display>(err: serde_path_to_error::Error<E>) -> ConfigError {
+    let field = match err.path().to_string() {
+        path if path == "." => "the top level".to_string(),
+        path => path,
+    };
+
+    ConfigError::Parse {
+        field,
+        reason: err.into_inner().to_string(),
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::arch::coord::Coord;
+use crate::arch::node::MeshNode;
+use crate::arch::router::VirtualChannels;
+use crate::arch::routing::RoutingAlgorithm;
+use crate::arch::topology::NodeId;
+use crate::comm::transfer::{Direction, next_phase, output_candidates, source_queue};
+use crate::sim::engine::{Cycle, EventQueue, SimAction};
+
+// One channel of a wait-for cycle, the packet at its head waits to leave at through waiting_on
+#[derive(Clone, PartialEq, Debug)]
+pub struct DeadlockedChannel {
+    pub id: usize,
+    pub at: Coord,
+    // Port the channel belongs to, Init for the local injection queue
+    pub input: Direction,
+    pub vc: usize,
+    pub waiting_on: Direction,
+}
+
+// Cycle of channels that each wait on the next one, the last waits on the first
+#[derive(Clone, PartialEq, Debug)]
+pub struct Deadlock {
+    pub cycle: Cycle,
+    pub channels: Vec<DeadlockedChannel>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Deadlock on cycle {}:", self.cycle)?;
+        for channel in &self.channels {
+            write!(
+                f,
+                " packet {} at {} ({:?} vc {}) waits on {:?} ->",
+                channel.id, channel.at, channel.input, channel.vc, channel.waiting_on
+            )?;
+        }
+        write!(f, " back to the start")
+    }
+}
+
+// A channel is a source of the send stage of a node: the inner buffer of one virtual channel of
+// one port, or the local queue
+type Channel = (NodeId, usize);
+
+// What the flit at the head of a channel is waiting on for one of its options
+#[derive(Copy, Clone)]
+enum Wait {
+    // Nothing, it can go as soon as the switch lets it
+    Nothing,
+    // Credits for a link buffer on the far side, which only come back once that channel moves
+    Downstream(Channel, Direction),
+    // An output channel held by another packet in the same node, freed once that one's tail goes
+    Holder(Channel, Direction),
+}
+
+// Builds the wait-for graph between channels and looks for channels that can never move again
+// - A channel moves if its head has an option that is free, or that waits on a channel that moves,
+// where a downstream channel also counts as moving while its inner buffer has room
+// - Credits still on their way back count as already there, so this holds at any point in a run
+// - Returns one cycle of stuck channels, or None if nothing is stuck or the stuck channels don't
+// wait on each other in a cycle, like a head with no link towards where it has to go
+pub fn detect(
+    nodes: &[MeshNode],
+    queue: &EventQueue,
+    routing: Option<&dyn RoutingAlgorithm>,
+    vcs: &VirtualChannels,
+) -> Option<Deadlock> {
+    let mut credits: Vec<Vec<Vec<usize>>> = nodes.iter().map(|node| node.credits.clone()).collect();
+    for action in queue.pending() {
+        if let SimAction::Credit { node, port, vc } = action {
+            credits[*node][*port][*vc] += 1;
+        }
+    }
+
+    // Options of every channel with a flit at its head, ids are node * stride + source
+    let stride = nodes
+        .iter()
+        .map(|node| node.port_count() * node.vc_count() + 1)
+        .max()
+        .unwrap_or(0);
+    let index = |(node, source): Channel| node * stride + source;
+    let mut waits: Vec<Option<Vec<Wait>>> = vec![None; nodes.len() * stride];
+
+    for (node_id, node) in nodes.iter().enumerate() {
+        let vc_count = node.vc_count();
+        for source in 0..node.port_count() * vc_count + 1 {
+            let Some(flit) = source_queue(node, source).front() else {
+                continue;
+            };
+
+            let options: Vec<(usize, usize)> = match (node.routes[source], &flit.packet) {
+                (Some(route), _) => vec![route],
+                // Sending fails on a packet without candidates before it could wait on anything
+                (None, Some(packet)) => output_candidates(node, &packet.header, routing)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|port| {
+                        let link = node.out_links[port].as_ref()?;
+                        let phase = next_phase(&packet.header, node.ports[port], link.dateline);
+                        Some(vcs.allowed(&packet.header, phase).map(move |vc| (port, vc)))
+                    })
+                    .flatten()
+                    .collect(),
+                (None, None) => continue,
+            };
+
+            let waiting = options
+                .into_iter()
+                .filter_map(|(port, vc)| {
+                    let link = node.out_links[port].as_ref()?;
+                    let dir = node.ports[port];
+                    let holder = (0..node.routes.len())
+                        .find(|holder| node.routes[*holder] == Some((port, vc)));
+
+                    Some(match holder {
+                        Some(holder) if holder != source => Wait::Holder((node_id, holder), dir),
+                        _ if credits[node_id][port][vc] > 0 => Wait::Nothing,
+                        _ => {
+                            let vc_count = nodes[link.dest].vc_count();
+                            Wait::Downstream((link.dest, link.dest_port * vc_count + vc), dir)
+                        }
+                    })
+                })
+                .collect();
+            waits[index((node_id, source))] = Some(waiting);
+        }
+    }
+
+    // Spread the ability to move backwards along the waits until nothing changes
+    let mut moves: Vec<bool> = waits.iter().map(Option::is_none).collect();
+    let buffer_room = |(node, source): Channel| {
+        let node = &nodes[node];
+        let vc_count = node.vc_count();
+        source < node.port_count() * vc_count
+            && node.inner_buffers[source / vc_count][source % vc_count].len() < node.inner_buffer
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for channel in 0..waits.len() {
+            if moves[channel] {
+                continue;
+            }
+
+            let free = waits[channel].iter().flatten().any(|wait| match *wait {
+                Wait::Nothing => true,
+                Wait::Downstream(next, _) => buffer_room(next) || moves[index(next)],
+                Wait::Holder(next, _) => moves[index(next)],
+            });
+            if free {
+                moves[channel] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // Stuck channels waiting on nothing else that is stuck, or only on such channels, block
+    // without being part of a cycle and are left out of the walk
+    let mut left_out = moves;
+    let waits_on_cycle = |wait: &Wait, left_out: &[bool]| match *wait {
+        Wait::Downstream(next, _) | Wait::Holder(next, _) => !left_out[index(next)],
+        Wait::Nothing => false,
+    };
+    changed = true;
+    while changed {
+        changed = false;
+        for channel in 0..waits.len() {
+            if !left_out[channel]
+                && !waits[channel]
+                    .iter()
+                    .flatten()
+                    .any(|wait| waits_on_cycle(wait, &left_out))
+            {
+                left_out[channel] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // Walk from any channel left until one comes up again, every one of them waits on another
+    let start = left_out.iter().position(|left_out| !left_out)?;
+    let mut path: Vec<(usize, Direction)> = Vec::new();
+    let mut channel = start;
+    loop {
+        if let Some(seen) = path.iter().position(|(seen, _)| *seen == channel) {
+            path.drain(..seen);
+            break;
+        }
+
+        let (next, dir) = waits[channel]
+            .iter()
+            .flatten()
+            .find_map(|wait| match *wait {
+                Wait::Downstream(next, dir) | Wait::Holder(next, dir) => {
+                    (!left_out[index(next)]).then_some((index(next), dir))
+                }
+                Wait::Nothing => None,
+            })?;
+        path.push((channel, dir));
+        channel = next;
+    }
+
+    let channels = path
+        .into_iter()
+        .map(|(channel, waiting_on)| {
+            let (node_id, source) = (channel / stride, channel % stride);
+            let node = &nodes[node_id];
+            let vc_count = node.vc_count();
+            let flit = source_queue(node, source)
+                .front()
+                .expect("A stuck channel has a flit at its head");
+            let (input, vc) = if source < node.port_count() * vc_count {
+                (node.ports[source / vc_count], source % vc_count)
+            } else {
+                (Direction::Init, 0)
+            };
+
+            DeadlockedChannel {
+                id: flit.id,
+                at: node.pos(),
+                input,
+                vc,
+                waiting_on,
+            }
+        })
+        .collect();
+
+    Some(Deadlock {
+        cycle: queue.now(),
+        channels,
+    })
+}
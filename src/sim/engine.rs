@@ -0,0 +1,131 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::arch::topology::{NodeId, PortId};
+use crate::comm::packet::{Flit, Packet};
+
+// Simulated time, one unit is one router clock cycle
+pub type Cycle = u64;
+
+pub enum SimAction {
+    // A packet is handed to the local injection queue of a node
+    Inject {
+        node: NodeId,
+        packet: Packet,
+    },
+    // A flit finishes traversing a link and lands in the link buffer of the receiving node
+    Arrive {
+        node: NodeId,
+        port: PortId,
+        flit: Flit,
+    },
+    // A credit for one slot of a link buffer comes back to the node on the sending end of the link
+    Credit {
+        node: NodeId,
+        port: PortId,
+        vc: usize,
+    },
+    // The node runs its receive and send stages for this cycle
+    Service {
+        node: NodeId,
+    },
+}
+
+impl SimAction {
+    // Everything that moves flits or credits into a node happens before any node is serviced in the same
+    // cycle, otherwise the outcome of a cycle would depend on the order things were scheduled in
+    fn phase(&self) -> u8 {
+        match self {
+            SimAction::Inject { .. } | SimAction::Arrive { .. } | SimAction::Credit { .. } => 0,
+            SimAction::Service { .. } => 1,
+        }
+    }
+}
+
+struct Scheduled {
+    cycle: Cycle,
+    phase: u8,
+    seq: u64,
+    action: SimAction,
+}
+
+impl Scheduled {
+    fn key(&self) -> (Cycle, u8, u64) {
+        (self.cycle, self.phase, self.seq)
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+// Global event queue keyed by simulated cycle
+// - Ties inside a cycle are broken by phase and then by insertion order, so a run only depends on
+// the order actions were scheduled in and never on the host
+#[derive(Default)]
+pub struct EventQueue {
+    heap: BinaryHeap<Reverse<Scheduled>>,
+    next_seq: u64,
+    now: Cycle,
+}
+
+impl EventQueue {
+    pub fn now(&self) -> Cycle {
+        self.now
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn schedule(&mut self, cycle: Cycle, action: SimAction) {
+        assert!(
+            cycle >= self.now,
+            "Tried to schedule an action at cycle {cycle} but the clock is already at {}",
+            self.now
+        );
+
+        let scheduled = Scheduled {
+            cycle,
+            phase: action.phase(),
+            seq: self.next_seq,
+            action,
+        };
+        self.next_seq += 1;
+        self.heap.push(Reverse(scheduled));
+    }
+
+    // Cycle of the next action, without popping it
+    pub fn next_cycle(&self) -> Option<Cycle> {
+        self.heap.peek().map(|Reverse(scheduled)| scheduled.cycle)
+    }
+
+    // Every action still waiting to happen, in no particular order
+    pub fn pending(&self) -> impl Iterator<Item = &SimAction> {
+        self.heap.iter().map(|Reverse(scheduled)| &scheduled.action)
+    }
+
+    // Pops the next action and advances the clock to it
+    pub fn pop(&mut self) -> Option<(Cycle, SimAction)> {
+        let Reverse(scheduled) = self.heap.pop()?;
+        self.now = scheduled.cycle;
+
+        Some((scheduled.cycle, scheduled.action))
+    }
+}
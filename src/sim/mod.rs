@@ -0,0 +1,8 @@
+pub mod config;
+pub mod deadlock;
+pub mod engine;
+pub mod monitor;
+pub mod network;
+pub mod stats;
+pub mod sweep;
+pub mod traffic;
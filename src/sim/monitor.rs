@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::io;
+
+use crate::arch::coord::Coord;
+use crate::arch::node::MeshNode;
+use crate::arch::topology::{NodeId, PortId};
+use crate::comm::packet::Flit;
+use crate::comm::transfer::Direction;
+use crate::sim::engine::Cycle;
+
+// Totals for one outgoing link since the network was built
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct LinkCounters {
+    pub flits: u64,
+    // Head flits, so packets that started crossing
+    pub packets: u64,
+    // Cycles on which at least one flit went out
+    pub busy_cycles: u64,
+    last_busy: Option<Cycle>,
+}
+
+// Counters of the link leaving from through dir towards to
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LinkUsage {
+    pub from: Coord,
+    pub dir: Direction,
+    pub to: Coord,
+    pub counters: LinkCounters,
+}
+
+impl LinkUsage {
+    // Share of cycles the link was busy
+    pub fn utilization(&self, cycles: Cycle) -> f64 {
+        match cycles {
+            0 => 0.0,
+            cycles => self.counters.busy_cycles as f64 / cycles as f64,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BufferKind {
+    // Flits that crossed the link into dir and wait to be received
+    Link,
+    // Flits received from dir waiting for their output
+    Inner,
+    // Flits injected at the node that haven't left it, dir is always Init
+    Local,
+}
+
+impl BufferKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BufferKind::Link => "link_buffer",
+            BufferKind::Inner => "inner_buffer",
+            BufferKind::Local => "local_queue",
+        }
+    }
+}
+
+// Buffer of a node, summed over its virtual channels
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BufferId {
+    pub at: Coord,
+    pub dir: Direction,
+    pub kind: BufferKind,
+}
+
+// Buffer state at the start of cycle, before anything on it was processed
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sample {
+    pub cycle: Cycle,
+    // Flits in each buffer, in the order of TimeSeries::buffers
+    pub occupancy: Vec<usize>,
+    // Flits carried so far by each link, in the order of TimeSeries::links
+    pub link_flits: Vec<u64>,
+}
+
+// Flit of packet id going out over a link, only kept while sampling
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LinkActivity {
+    pub cycle: Cycle,
+    // Index into TimeSeries::links
+    pub link: usize,
+    pub id: usize,
+}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct TimeSeries {
+    pub buffers: Vec<BufferId>,
+    // Node and direction of every outgoing link
+    pub links: Vec<(Coord, Direction)>,
+    pub samples: Vec<Sample>,
+    // Every flit sent while sampling, in the order they went out
+    pub activity: Vec<LinkActivity>,
+}
+
+impl TimeSeries {
+    // Flits in one buffer over time
+    pub fn buffer(&self, id: BufferId) -> Option<impl Iterator<Item = (Cycle, usize)> + '_> {
+        let index = self.buffers.iter().position(|&buffer| buffer == id)?;
+        Some(
+            self.samples
+                .iter()
+                .map(move |sample| (sample.cycle, sample.occupancy[index])),
+        )
+    }
+
+    // One line per sample and series, link_flits is the running total of the link
+    pub fn write_csv(&self, mut out: impl io::Write) -> io::Result<()> {
+        writeln!(out, "cycle,x,y,z,dir,series,value")?;
+        for sample in &self.samples {
+            for (buffer, flits) in self.buffers.iter().zip(&sample.occupancy) {
+                let Coord { x, y, z } = buffer.at;
+                writeln!(
+                    out,
+                    "{},{x},{y},{z},{:?},{},{flits}",
+                    sample.cycle,
+                    buffer.dir,
+                    buffer.kind.name()
+                )?;
+            }
+            for ((at, dir), flits) in self.links.iter().zip(&sample.link_flits) {
+                let Coord { x, y, z } = at;
+                writeln!(
+                    out,
+                    "{},{x},{y},{z},{dir:?},link_flits,{flits}",
+                    sample.cycle
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Keeps the link counters and samples the buffers of a network every interval cycles
+#[derive(Default)]
+pub struct Monitor {
+    // Indexed by node, then output port
+    links: Vec<Vec<LinkCounters>>,
+    // Node and port behind each entry of series.links
+    link_ports: Vec<(NodeId, PortId)>,
+    // Inverse of link_ports, indexed by node, then output port
+    link_index: Vec<Vec<Option<usize>>>,
+    interval: Option<Cycle>,
+    next_sample: Cycle,
+    series: TimeSeries,
+}
+
+impl Monitor {
+    pub fn new(nodes: &[MeshNode]) -> Self {
+        let mut monitor = Self {
+            links: nodes
+                .iter()
+                .map(|node| vec![LinkCounters::default(); node.port_count()])
+                .collect(),
+            link_index: nodes
+                .iter()
+                .map(|node| vec![None; node.port_count()])
+                .collect(),
+            ..Self::default()
+        };
+
+        for (id, node) in nodes.iter().enumerate() {
+            let at = node.pos();
+            for (port, &dir) in node.ports.iter().enumerate() {
+                for kind in [BufferKind::Link, BufferKind::Inner] {
+                    monitor.series.buffers.push(BufferId { at, dir, kind });
+                }
+                if node.out_links[port].is_some() {
+                    monitor.link_index[id][port] = Some(monitor.series.links.len());
+                    monitor.series.links.push((at, dir));
+                    monitor.link_ports.push((id, port));
+                }
+            }
+            monitor.series.buffers.push(BufferId {
+                at,
+                dir: Direction::Init,
+                kind: BufferKind::Local,
+            });
+        }
+
+        monitor
+    }
+
+    // Samples every interval cycles from the current one, None stops sampling and an interval of 0
+    // is raised to 1
+    pub fn set_interval(&mut self, interval: Option<Cycle>, now: Cycle) {
+        self.interval = interval.map(|interval| interval.max(1));
+        self.next_sample = now;
+    }
+
+    pub fn record_flit(&mut self, node: NodeId, port: PortId, flit: &Flit, cycle: Cycle) {
+        let counters = &mut self.links[node][port];
+        counters.flits += 1;
+        if flit.kind.is_head() {
+            counters.packets += 1;
+        }
+        if counters.last_busy != Some(cycle) {
+            counters.busy_cycles += 1;
+            counters.last_busy = Some(cycle);
+        }
+
+        if self.interval.is_some()
+            && let Some(link) = self.link_index[node][port]
+        {
+            self.series.activity.push(LinkActivity {
+                cycle,
+                link,
+                id: flit.id,
+            });
+        }
+    }
+
+    // Takes every sample due up to and including cycle, called before cycle is processed
+    pub fn sample_until(&mut self, nodes: &[MeshNode], cycle: Cycle) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+
+        while self.next_sample <= cycle {
+            let occupancy = nodes
+                .iter()
+                .flat_map(|node| {
+                    (0..node.port_count())
+                        .flat_map(move |port| {
+                            [
+                                buffered(&node.link_buffers[port]),
+                                buffered(&node.inner_buffers[port]),
+                            ]
+                        })
+                        .chain([node.local_queue.len()])
+                })
+                .collect();
+            let link_flits = self
+                .link_ports
+                .iter()
+                .map(|&(node, port)| self.links[node][port].flits)
+                .collect();
+
+            self.series.samples.push(Sample {
+                cycle: self.next_sample,
+                occupancy,
+                link_flits,
+            });
+            self.next_sample += interval;
+        }
+    }
+
+    pub fn link_usage(&self, nodes: &[MeshNode]) -> Vec<LinkUsage> {
+        self.link_ports
+            .iter()
+            .map(|&(node, port)| {
+                let link = nodes[node].out_links[port].expect("Monitored link should exist");
+                LinkUsage {
+                    from: nodes[node].pos(),
+                    dir: nodes[node].ports[port],
+                    to: nodes[link.dest].pos(),
+                    counters: self.links[node][port],
+                }
+            })
+            .collect()
+    }
+
+    pub fn time_series(&self) -> &TimeSeries {
+        &self.series
+    }
+}
+
+// Flits across the virtual channels of a port
+fn buffered(buffers: &[VecDeque<Flit>]) -> usize {
+    buffers.iter().map(VecDeque::len).sum()
+}
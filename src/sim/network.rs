@@ -0,0 +1,370 @@
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::arch::grid::NODE_RATE;
+use crate::arch::node::{MeshNode, NodeParams};
+use crate::arch::router::{RouterPipeline, VirtualChannels};
+use crate::arch::routing::RoutingAlgorithm;
+use crate::arch::topology::{NodeId, PortId, Topology};
+use crate::comm::packet::{Event, Flit, Packet};
+use crate::comm::transfer::{NodeCommError, next_ready, receive_packets, send_packet};
+use crate::sim::deadlock::{self, Deadlock};
+use crate::sim::engine::{Cycle, EventQueue, SimAction};
+use crate::sim::monitor::{LinkUsage, Monitor, TimeSeries};
+
+// Runs the per node send/receive machinery over any topology
+// - Nothing moves until the network is run, every node is driven by the global event queue so two
+// runs with the same injections produce exactly the same events
+#[derive(Default)]
+pub struct Network {
+    nodes: Vec<MeshNode>,
+    queue: EventQueue,
+    event_tx: Option<UnboundedSender<Event>>,
+    packet_count: usize,
+    // None keeps the simple model where a packet crosses a node within the cycle it is received
+    pipeline: Option<RouterPipeline>,
+    // Picks the next hop of packets injected without a source route
+    routing: Option<Arc<dyn RoutingAlgorithm>>,
+    vcs: VirtualChannels,
+    // Cycles between deadlock checks while running, None only checks once the network stalls
+    deadlock_check: Option<Cycle>,
+    monitor: Monitor,
+}
+
+impl Network {
+    pub fn new(
+        topology: &dyn Topology,
+        pipeline: Option<RouterPipeline>,
+    ) -> (Self, UnboundedReceiver<Event>) {
+        let (event_tx, event_rx) = mpsc::unbounded_channel::<Event>();
+
+        let mut nodes: Vec<MeshNode> = (0..topology.node_count())
+            .map(|node| {
+                let ports = (0..topology.port_count(node))
+                    .map(|port| topology.port_dir(node, port))
+                    .collect();
+                MeshNode::new(topology.coord(node), ports, NODE_RATE, NODE_RATE)
+            })
+            .collect();
+
+        for link in topology.links() {
+            let slots = nodes[link.dest].link_buffer;
+            nodes[link.src].connect(link.src_port, link, slots);
+            nodes[link.dest].in_links[link.dest_port] = Some((link.src, link.src_port));
+        }
+
+        let network = Self {
+            monitor: Monitor::new(&nodes),
+            nodes,
+            queue: EventQueue::default(),
+            event_tx: Some(event_tx),
+            packet_count: 0,
+            pipeline,
+            routing: None,
+            vcs: VirtualChannels::default(),
+            deadlock_check: None,
+        };
+
+        (network, event_rx)
+    }
+
+    pub fn node(&self, node: NodeId) -> &MeshNode {
+        &self.nodes[node]
+    }
+
+    // Replaces the channel events are sent on, the receiver new returned is closed
+    pub fn set_event_sink(&mut self, event_tx: UnboundedSender<Event>) {
+        self.event_tx = Some(event_tx);
+    }
+
+    // Every node, indexed by NodeId
+    pub fn nodes(&self) -> &[MeshNode] {
+        &self.nodes
+    }
+
+    pub fn cycle(&self) -> Cycle {
+        self.queue.now()
+    }
+
+    // Nothing left to process, run would return straight away
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // Splits every link into vcs.count virtual channels, has to happen before anything is injected
+    pub fn set_virtual_channels(&mut self, vcs: VirtualChannels) {
+        for node in &mut self.nodes {
+            node.set_vc_count(vcs.count);
+        }
+        for node in 0..self.nodes.len() {
+            self.refill_upstream(node);
+        }
+        self.vcs = vcs;
+    }
+
+    // Changes the rates and buffer depths of a node, has to happen before anything is injected
+    pub fn set_node_params(&mut self, node: NodeId, params: NodeParams) {
+        self.nodes[node].set_params(params);
+        self.refill_upstream(node);
+    }
+
+    // Hands the nodes sending to node a credit for every slot of its link buffers
+    fn refill_upstream(&mut self, node: NodeId) {
+        let slots = self.nodes[node].link_buffer;
+        for port in 0..self.nodes[node].port_count() {
+            if let Some((src, src_port)) = self.nodes[node].in_links[port] {
+                self.nodes[src].refill(src_port, slots);
+            }
+        }
+    }
+
+    // Lets packets be injected with an empty path, every node they pass then asks routing where
+    // to go next
+    // - The algorithm only knows grid directions, so this is meant for topologies whose ports are
+    // labelled with them
+    pub fn set_routing(&mut self, routing: Option<Arc<dyn RoutingAlgorithm>>) {
+        self.routing = routing;
+    }
+
+    // Routes the packet through the topology the network was built from and injects it
+    pub fn send_packet(
+        &mut self,
+        topology: &dyn Topology,
+        packet: Packet,
+    ) -> Result<usize, NodeCommError> {
+        let (src_pos, dest_pos) = (packet.header.cur_pos, packet.header.dest_pos);
+        let src = topology.node_id(src_pos)?;
+        let dest = topology.node_id(dest_pos)?;
+        let path = topology.route(src, dest).ok_or(NodeCommError::NoRoute {
+            src: src_pos,
+            dest: dest_pos,
+        })?;
+
+        Ok(self.inject(src, packet, path))
+    }
+
+    // Queues the packet for injection at src on the current cycle, it follows path from there or
+    // is routed hop by hop if path is empty
+    // - Returns the id the packet was given
+    pub fn inject(&mut self, src: NodeId, packet: Packet, path: Vec<PortId>) -> usize {
+        self.inject_at(src, packet, path, self.queue.now())
+    }
+
+    // Same as inject but the packet only enters the node on cycle, which can't be in the past
+    pub fn inject_at(
+        &mut self,
+        src: NodeId,
+        mut packet: Packet,
+        path: Vec<PortId>,
+        cycle: Cycle,
+    ) -> usize {
+        packet.header.id = self.packet_count;
+        self.packet_count += 1;
+        packet.header.path = path;
+        packet.header.created = cycle;
+
+        let id = packet.header.id;
+        self.queue
+            .schedule(cycle, SimAction::Inject { node: src, packet });
+
+        id
+    }
+
+    // Also looks for deadlocks every interval cycles while running, which catches a stuck part of
+    // the network while the rest keeps going
+    pub fn set_deadlock_check(&mut self, interval: Option<Cycle>) {
+        self.deadlock_check = interval;
+    }
+
+    // Samples the occupancy of every buffer every interval cycles from now on while running, None
+    // stops sampling and Some(0) is taken as Some(1)
+    pub fn set_sample_interval(&mut self, interval: Option<Cycle>) {
+        self.monitor.set_interval(interval, self.queue.now());
+    }
+
+    pub fn link_usage(&self) -> Vec<LinkUsage> {
+        self.monitor.link_usage(&self.nodes)
+    }
+
+    pub fn time_series(&self) -> &TimeSeries {
+        self.monitor.time_series()
+    }
+
+    pub fn detect_deadlock(&self) -> Option<Deadlock> {
+        deadlock::detect(&self.nodes, &self.queue, self.routing.as_deref(), &self.vcs)
+    }
+
+    // Processes events until the network has nothing left to do and returns the cycle it went
+    // idle on
+    // - Fails with the deadlock if it stalls with flits still buffered, or with Stalled when those
+    // flits are stuck without a deadlock cycle
+    pub fn run(&mut self) -> Result<Cycle, NodeCommError> {
+        self.run_until(Cycle::MAX)
+    }
+
+    // Processes everything scheduled on the next cycle that has anything, returns that cycle or
+    // None if the network is idle
+    pub fn step(&mut self) -> Result<Option<Cycle>, NodeCommError> {
+        let Some(next) = self.queue.next_cycle() else {
+            return Ok(None);
+        };
+
+        self.run_until(next + 1)?;
+        Ok(Some(next))
+    }
+
+    // Same as run but stops before cycle end, anything scheduled from then on stays queued
+    pub fn run_until(&mut self, end: Cycle) -> Result<Cycle, NodeCommError> {
+        let mut last_check = self.queue.now();
+
+        while let Some(next) = self.queue.next_cycle()
+            && next < end
+        {
+            // Checked between cycles so no cycle is half processed
+            if let Some(interval) = self.deadlock_check
+                && next >= last_check + interval
+            {
+                last_check = next;
+                if let Some(deadlock) = self.detect_deadlock() {
+                    return Err(NodeCommError::Deadlock(deadlock));
+                }
+            }
+
+            self.monitor.sample_until(&self.nodes, next);
+            if let Some((cycle, action)) = self.queue.pop() {
+                self.process(cycle, action)?;
+            }
+        }
+
+        let stuck = match self.queue.is_empty() {
+            true => self.nodes.iter().filter(|node| node.has_work()).count(),
+            false => 0,
+        };
+        if stuck > 0 {
+            return Err(match self.detect_deadlock() {
+                Some(deadlock) => NodeCommError::Deadlock(deadlock),
+                None => NodeCommError::Stalled {
+                    cycle: self.queue.now(),
+                    nodes: stuck,
+                },
+            });
+        }
+        Ok(self.queue.now())
+    }
+
+    fn process(&mut self, cycle: Cycle, action: SimAction) -> Result<(), NodeCommError> {
+        let event_tx = self
+            .event_tx
+            .clone()
+            .expect("Network should be initialized before it is run");
+
+        match action {
+            SimAction::Inject { node, mut packet } => {
+                if packet.header.cur_pos == packet.header.dest_pos {
+                    event_tx.send(Event::PacketArrived {
+                        id: packet.header.id,
+                        at: packet.header.cur_pos,
+                        src: packet.header.src_pos,
+                        dest: packet.header.dest_pos,
+                        created: packet.header.created,
+                        hops: 0,
+                        flits: packet.header.flits,
+                        class: packet.header.class,
+                        cycle,
+                    })?;
+                    return Ok(());
+                }
+
+                packet.header.hop_start = cycle;
+                self.nodes[node].local_queue.extend(Flit::segment(packet));
+                self.wake(node, cycle);
+            }
+            SimAction::Arrive { node, port, flit } => {
+                self.nodes[node].link_buffers[port][flit.vc].push_back(flit);
+                self.wake(node, cycle);
+            }
+            SimAction::Credit { node, port, vc } => {
+                self.nodes[node].credits[port][vc] += 1;
+                self.wake(node, cycle);
+            }
+            SimAction::Service { node } => {
+                if self.nodes[node].last_service == Some(cycle) {
+                    return Ok(());
+                }
+                self.nodes[node].last_service = Some(cycle);
+
+                // Every slot freed in a link buffer goes back upstream as a credit
+                let freed = receive_packets(&mut self.nodes[node], &event_tx, cycle)?;
+                for &(port, vc) in &freed {
+                    if let Some((upstream, upstream_port)) = self.nodes[node].in_links[port] {
+                        let link = self.nodes[upstream].out_links[upstream_port]
+                            .expect("Incoming link should exist on the sending end");
+                        self.queue.schedule(
+                            cycle + link.params.credit_latency,
+                            SimAction::Credit {
+                                node: upstream,
+                                port: upstream_port,
+                                vc,
+                            },
+                        );
+                    }
+                }
+
+                let pipeline = self.pipeline;
+                let sent = send_packet(
+                    &mut self.nodes[node],
+                    self.routing.as_deref(),
+                    &self.vcs,
+                    pipeline.as_ref(),
+                    &event_tx,
+                    cycle,
+                )?;
+                let progress = !freed.is_empty() || !sent.is_empty();
+
+                for (out_port, flit) in sent {
+                    self.monitor.record_flit(node, out_port, &flit, cycle);
+                    let link = self.nodes[node].out_links[out_port]
+                        .expect("Path should only use ports with a link");
+                    let link_latency = link.params.latency;
+                    let departure_delay = match pipeline {
+                        Some(p) if flit.kind.is_head() => p.departure_delay(link_latency),
+                        Some(p) => p.body_delay(link_latency),
+                        None => link_latency,
+                    };
+
+                    self.queue.schedule(
+                        cycle + departure_delay,
+                        SimAction::Arrive {
+                            node: link.dest,
+                            port: link.dest_port,
+                            flit,
+                        },
+                    );
+                }
+
+                // A node that made no progress is blocked, it gets woken again once a flit lands
+                // or a credit comes back
+                if progress && self.nodes[node].has_work() {
+                    self.wake(node, cycle + 1);
+                } else if let Some(pipeline) = pipeline
+                    && let Some(ready) = next_ready(&self.nodes[node], &pipeline, cycle)
+                {
+                    self.wake(node, ready);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Schedules a service for the node, a node is serviced at most once per cycle
+    fn wake(&mut self, node: NodeId, cycle: Cycle) {
+        let at = match self.nodes[node].last_service {
+            Some(last) if last >= cycle => last + 1,
+            _ => cycle,
+        };
+
+        self.queue.schedule(at, SimAction::Service { node });
+    }
+}
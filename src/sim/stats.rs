@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::arch::coord::Coord;
+use crate::comm::packet::Event;
+use crate::sim::engine::Cycle;
+
+// Every sample of one quantity, kept around so any percentile can be asked for later
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Distribution {
+    samples: Vec<u64>,
+}
+
+impl Distribution {
+    pub fn record(&mut self, value: u64) {
+        self.samples.push(value);
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (!self.samples.is_empty())
+            .then(|| self.samples.iter().sum::<u64>() as f64 / self.samples.len() as f64)
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.samples.iter().copied().min()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.samples.iter().copied().max()
+    }
+
+    // Nearest rank percentile, p between 0 and 100
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.max(1) - 1).copied()
+    }
+}
+
+// Everything recorded for the packets going from one source to one destination
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct PairStats {
+    pub latency: Distribution,
+    pub hops: Distribution,
+}
+
+// Builds latency, hop and throughput figures out of the events a grid sends
+// - Latency runs from the cycle the packet was created to the cycle its tail arrived, split into
+// queuing delay until the head leaves the source and network latency after that
+// - Throughput is accepted packets per cycle, measured from the first to the last event seen
+// - With a measurement window only packets created inside it count towards latency and hops, and
+// only arrivals inside it towards throughput
+#[derive(Default, Debug)]
+pub struct Stats {
+    // Cycle the head of every packet still in flight left its source
+    departed: HashMap<usize, Cycle>,
+    latency: Distribution,
+    queuing: Distribution,
+    network: Distribution,
+    hops: Distribution,
+    accepted: BTreeMap<Coord, usize>,
+    pairs: BTreeMap<(Coord, Coord), PairStats>,
+    first_cycle: Option<Cycle>,
+    last_cycle: Cycle,
+    window: Option<Range<Cycle>>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_window(window: Range<Cycle>) -> Self {
+        Self {
+            window: Some(window),
+            ..Self::default()
+        }
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        let cycle = event.cycle();
+        self.first_cycle = Some(self.first_cycle.map_or(cycle, |first| first.min(cycle)));
+        self.last_cycle = self.last_cycle.max(cycle);
+
+        match *event {
+            Event::PacketSent { id, cycle, .. } => {
+                self.departed.entry(id).or_insert(cycle);
+            }
+            Event::PacketArrived {
+                id,
+                at,
+                src,
+                created,
+                hops,
+                cycle,
+                ..
+            } => {
+                // A packet for its own source never leaves it
+                let departed = self.departed.remove(&id).unwrap_or(created);
+                let latency = cycle - created;
+
+                let inside = |cycle| self.window.as_ref().is_none_or(|w| w.contains(&cycle));
+                if inside(cycle) {
+                    *self.accepted.entry(at).or_default() += 1;
+                }
+                if !inside(created) {
+                    return;
+                }
+
+                self.latency.record(latency);
+                self.queuing.record(departed - created);
+                self.network.record(cycle - departed);
+                self.hops.record(hops as u64);
+
+                let pair = self.pairs.entry((src, at)).or_default();
+                pair.latency.record(latency);
+                pair.hops.record(hops as u64);
+            }
+            _ => {}
+        }
+    }
+
+    // Records every event already waiting on the receiver
+    pub fn drain(&mut self, event_rx: &mut UnboundedReceiver<Event>) {
+        while let Ok(event) = event_rx.try_recv() {
+            self.record(&event);
+        }
+    }
+
+    // Creation to arrival
+    pub fn latency(&self) -> &Distribution {
+        &self.latency
+    }
+
+    // Creation until the head left the source
+    pub fn queuing_delay(&self) -> &Distribution {
+        &self.queuing
+    }
+
+    // Head leaving the source to the tail arriving
+    pub fn network_latency(&self) -> &Distribution {
+        &self.network
+    }
+
+    pub fn hops(&self) -> &Distribution {
+        &self.hops
+    }
+
+    // Packets that arrived and count towards latency
+    pub fn packets_arrived(&self) -> usize {
+        self.latency.count()
+    }
+
+    // Packets that count towards throughput
+    pub fn packets_accepted(&self) -> usize {
+        self.accepted.values().sum()
+    }
+
+    // Packets whose head left the source but that haven't arrived yet
+    // - Packets still queued at their source send no event before they leave, so they aren't in
+    // here
+    pub fn in_network(&self) -> usize {
+        self.departed.len()
+    }
+
+    pub fn cycles(&self) -> Cycle {
+        match &self.window {
+            Some(window) => window.end - window.start,
+            None => self
+                .first_cycle
+                .map_or(0, |first| self.last_cycle - first + 1),
+        }
+    }
+
+    // Packets accepted by the whole network per cycle
+    pub fn throughput(&self) -> f64 {
+        match self.cycles() {
+            0 => 0.0,
+            cycles => self.packets_accepted() as f64 / cycles as f64,
+        }
+    }
+
+    // Packets accepted per cycle by every node that received any
+    pub fn node_throughput(&self) -> BTreeMap<Coord, f64> {
+        let cycles = self.cycles().max(1) as f64;
+        self.accepted
+            .iter()
+            .map(|(&node, &accepted)| (node, accepted as f64 / cycles))
+            .collect()
+    }
+
+    // Mean latency of the packets that arrived at every node
+    pub fn node_latency(&self) -> BTreeMap<Coord, f64> {
+        let mut totals: BTreeMap<Coord, (f64, usize)> = BTreeMap::new();
+        for (&(_, dest), pair) in &self.pairs {
+            let total = totals.entry(dest).or_default();
+            total.0 += pair.latency.mean().unwrap_or(0.0) * pair.latency.count() as f64;
+            total.1 += pair.latency.count();
+        }
+
+        totals
+            .into_iter()
+            .map(|(node, (sum, count))| (node, sum / count as f64))
+            .collect()
+    }
+
+    pub fn pair(&self, src: Coord, dest: Coord) -> Option<&PairStats> {
+        self.pairs.get(&(src, dest))
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = (&(Coord, Coord), &PairStats)> {
+        self.pairs.iter()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latency = &self.latency;
+        writeln!(
+            f,
+            "{} packets over {} cycles, {:.4} packets/cycle",
+            self.packets_accepted(),
+            self.cycles(),
+            self.throughput()
+        )?;
+
+        let Some(mean) = latency.mean() else {
+            return Ok(());
+        };
+        writeln!(
+            f,
+            "latency: mean {:.2} min {} max {} p50 {} p90 {} p99 {}",
+            mean,
+            latency.min().unwrap_or(0),
+            latency.max().unwrap_or(0),
+            latency.percentile(50.0).unwrap_or(0),
+            latency.percentile(90.0).unwrap_or(0),
+            latency.percentile(99.0).unwrap_or(0)
+        )?;
+        writeln!(
+            f,
+            "queuing: mean {:.2}, network: mean {:.2}, hops: mean {:.2}",
+            self.queuing.mean().unwrap_or(0.0),
+            self.network.mean().unwrap_or(0.0),
+            self.hops.mean().unwrap_or(0.0)
+        )
+    }
+}
@@ -0,0 +1,177 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::arch::coord::Dim;
+use crate::arch::grid::Grid;
+use crate::comm::transfer::NodeCommError;
+use crate::sim::engine::Cycle;
+use crate::sim::stats::Stats;
+use crate::sim::traffic::{InjectionProcess, TrafficError, TrafficGenerator, TrafficPattern};
+
+// Latency against offered load, one run of the grid per injection rate
+// - Every run injects through warmup, measure and drain, only packets created during measure are
+// measured and they all have to arrive before drain is over
+// - A rate is saturated when they don't, or when their mean latency is more than
+// saturation_latency times the zero load latency, the latency of the first rate any packet
+// arrived at. The sweep stops at the first saturated rate
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub dims: (Dim, Dim, Dim),
+    pub pattern: TrafficPattern,
+    pub process: InjectionProcess,
+    // Offered load of every node in packets per cycle, lowest first
+    pub rates: Vec<f64>,
+    pub flits: usize,
+    pub warmup: Cycle,
+    pub measure: Cycle,
+    pub drain: Cycle,
+    pub saturation_latency: f64,
+    pub seed: u64,
+}
+
+impl Sweep {
+    pub fn new(dims: (Dim, Dim, Dim), pattern: TrafficPattern, rates: Vec<f64>) -> Self {
+        Self {
+            dims,
+            pattern,
+            process: InjectionProcess::Bernoulli,
+            rates,
+            flits: 1,
+            warmup: 1000,
+            measure: 1000,
+            drain: 2000,
+            saturation_latency: 3.0,
+            seed: 0,
+        }
+    }
+
+    // Runs every rate on grid, which is rebuilt with init_grid_3d for each of them so anything set
+    // on it beforehand (topology, routing, virtual channels...) carries over
+    pub fn run(&self, grid: &mut Grid) -> Result<SweepCurve, TrafficError> {
+        let mut curve = SweepCurve::default();
+        let mut zero_load = None;
+        let mut below = None;
+
+        for &rate in &self.rates {
+            let point = self.run_rate(grid, rate, zero_load)?;
+            // A rate so low nothing arrived has no latency to compare against
+            if zero_load.is_none() && point.packets > point.unfinished && point.latency > 0.0 {
+                zero_load = Some(point.latency);
+            }
+            let saturated = point.saturated;
+            curve.points.push(point);
+
+            if saturated {
+                curve.saturation = below;
+                break;
+            }
+            below = Some(rate);
+        }
+
+        Ok(curve)
+    }
+
+    fn run_rate(
+        &self,
+        grid: &mut Grid,
+        rate: f64,
+        zero_load: Option<f64>,
+    ) -> Result<SweepPoint, TrafficError> {
+        let (width, height, depth) = self.dims;
+        let mut event_rx = grid
+            .init_grid_3d(width, height, depth)
+            .map_err(NodeCommError::from)?;
+        let mut generator =
+            TrafficGenerator::new(self.pattern.clone(), self.process, rate, self.seed);
+        generator.set_packet_flits(self.flits);
+
+        // Traffic keeps coming while draining so the measured packets see the same load
+        let (measure_start, measure_end) = (self.warmup, self.warmup + self.measure);
+        generator.inject(grid, 0..measure_start)?;
+        let measured = generator.inject(grid, measure_start..measure_end)?;
+        generator.inject(grid, measure_end..measure_end + self.drain)?;
+        grid.run_until(measure_end + self.drain)?;
+
+        let mut stats = Stats::with_window(measure_start..measure_end);
+        stats.drain(&mut event_rx);
+
+        let nodes = width as f64 * height as f64 * depth as f64;
+        let latency = stats.latency().mean().unwrap_or(0.0);
+        let unfinished = measured - stats.packets_arrived();
+        let saturated = unfinished > 0
+            || zero_load.is_some_and(|zero_load| latency > zero_load * self.saturation_latency);
+
+        Ok(SweepPoint {
+            rate,
+            offered: measured as f64 / nodes / self.measure as f64,
+            accepted: stats.throughput() / nodes,
+            latency,
+            p50: stats.latency().percentile(50.0).unwrap_or(0),
+            p99: stats.latency().percentile(99.0).unwrap_or(0),
+            network_latency: stats.network_latency().mean().unwrap_or(0.0),
+            queuing_delay: stats.queuing_delay().mean().unwrap_or(0.0),
+            hops: stats.hops().mean().unwrap_or(0.0),
+            packets: measured,
+            unfinished,
+            saturated,
+        })
+    }
+}
+
+// One rate of a sweep, loads are in packets per node per cycle and latencies cover the measured
+// packets that arrived
+#[derive(Clone, PartialEq, Serialize, Debug)]
+pub struct SweepPoint {
+    pub rate: f64,
+    // Load the generator actually injected during measure
+    pub offered: f64,
+    pub accepted: f64,
+    pub latency: f64,
+    pub p50: u64,
+    pub p99: u64,
+    pub network_latency: f64,
+    pub queuing_delay: f64,
+    pub hops: f64,
+    pub packets: usize,
+    // Measured packets still in the network when drain ran out
+    pub unfinished: usize,
+    pub saturated: bool,
+}
+
+#[derive(Clone, PartialEq, Default, Serialize, Debug)]
+pub struct SweepCurve {
+    pub points: Vec<SweepPoint>,
+    // Highest rate before the first saturated one, None when no rate saturated or the lowest did
+    // already, the last point tells the two apart
+    pub saturation: Option<f64>,
+}
+
+impl SweepCurve {
+    pub fn write_csv(&self, mut out: impl io::Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "rate,offered,accepted,latency,p50,p99,network_latency,queuing_delay,hops,packets,unfinished,saturated"
+        )?;
+        for point in &self.points {
+            writeln!(
+                out,
+                "{},{:.6},{:.6},{:.3},{},{},{:.3},{:.3},{:.3},{},{},{}",
+                point.rate,
+                point.offered,
+                point.accepted,
+                point.latency,
+                point.p50,
+                point.p99,
+                point.network_latency,
+                point.queuing_delay,
+                point.hops,
+                point.packets,
+                point.unfinished,
+                point.saturated
+            )?;
+        }
+
+        Ok(())
+    }
+}
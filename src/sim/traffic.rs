@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::Grid;
+use crate::comm::packet::{Packet, PacketData};
+use crate::comm::transfer::NodeCommError;
+use crate::sim::engine::Cycle;
+
+#[derive(Error, Debug)]
+pub enum TrafficError {
+    #[error("Transpose needs a square grid, not {width}x{height}")]
+    NotSquare { width: Dim, height: Dim },
+
+    #[error("{0}")]
+    Network(#[from] NodeCommError),
+}
+
+// Where every source sends its packets, for a grid of the given dimensions
+// - Coordinate patterns work per dimension, the bit patterns on the node index (layer major, then
+// row major) and expect the node count to be a power of two, anything past the last node wraps
+// around
+// - A source that a pattern maps onto itself doesn't inject anything
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficPattern {
+    // Any other node with the same probability
+    #[default]
+    Uniform,
+    // (x, y) -> (y, x), only on a square grid
+    Transpose,
+    // Every coordinate mirrored, (x, y) -> (width - 1 - x, height - 1 - y)
+    BitComplement,
+    // Bits of the node index reversed
+    BitReverse,
+    // Bits of the node index rotated left by one
+    Shuffle,
+    // Just under halfway round every dimension, (x + ceil(width / 2) - 1) mod width
+    Tornado,
+    // One step further in every dimension, (x + 1) mod width
+    Neighbor,
+    // fraction of the packets go to one of the hotspots, the rest is uniform
+    Hotspot {
+        hotspots: Vec<Coord>,
+        fraction: f64,
+    },
+}
+
+impl TrafficPattern {
+    // Whether the pattern is defined on a grid of the given dimensions
+    pub fn check(&self, dims: (Dim, Dim, Dim)) -> Result<(), TrafficError> {
+        let (width, height, _) = dims;
+        match self {
+            TrafficPattern::Transpose if width != height => {
+                Err(TrafficError::NotSquare { width, height })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn destination(&self, src: Coord, dims: (Dim, Dim, Dim), rng: &mut impl Rng) -> Coord {
+        let (width, height, depth) = dims;
+        let count = width as usize * height as usize * depth as usize;
+        let index = |pos: Coord| {
+            (pos.z as usize * height as usize + pos.y as usize) * width as usize + pos.x as usize
+        };
+        let coord = |index: usize| {
+            Coord::new(
+                (index % width as usize) as Dim,
+                (index / width as usize % height as usize) as Dim,
+                (index / (width as usize * height as usize)) as Dim,
+            )
+        };
+        let bits = count.next_power_of_two().trailing_zeros();
+        let mask = (1usize << bits) - 1;
+
+        match self {
+            TrafficPattern::Uniform => {
+                if count < 2 {
+                    return src;
+                }
+                // Skip over the source so every other node is equally likely
+                let pick = rng.random_range(0..count - 1);
+                coord(if pick >= index(src) { pick + 1 } else { pick })
+            }
+            TrafficPattern::Transpose => Coord::new(src.y % width, src.x % height, src.z),
+            TrafficPattern::BitComplement => {
+                Coord::new(width - 1 - src.x, height - 1 - src.y, depth - 1 - src.z)
+            }
+            TrafficPattern::BitReverse => {
+                let reversed = index(src).reverse_bits().checked_shr(usize::BITS - bits);
+                coord(reversed.unwrap_or(0) % count)
+            }
+            TrafficPattern::Shuffle => {
+                let i = index(src);
+                let rotated = ((i << 1) | (i >> bits.saturating_sub(1))) & mask;
+                coord(rotated % count)
+            }
+            TrafficPattern::Tornado => {
+                // Widened so the sum can't overflow on the largest sides
+                let shift = |c: Dim, size: Dim| {
+                    let (c, size) = (c as usize, size as usize);
+                    ((c + size.div_ceil(2) - 1) % size) as Dim
+                };
+                Coord::new(
+                    shift(src.x, width),
+                    shift(src.y, height),
+                    shift(src.z, depth),
+                )
+            }
+            TrafficPattern::Neighbor => Coord::new(
+                (src.x + 1) % width,
+                (src.y + 1) % height,
+                (src.z + 1) % depth,
+            ),
+            TrafficPattern::Hotspot { hotspots, fraction } => {
+                if !hotspots.is_empty() && rng.random_bool(fraction.clamp(0.0, 1.0)) {
+                    hotspots[rng.random_range(0..hotspots.len())]
+                } else {
+                    TrafficPattern::Uniform.destination(src, dims, rng)
+                }
+            }
+        }
+    }
+}
+
+// How many packets a node injects on each cycle, rate is the average in packets per cycle
+#[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectionProcess {
+    // At most one packet a cycle, with probability rate
+    #[default]
+    Bernoulli,
+    // Poisson distributed number of packets a cycle, can be more than one
+    Poisson,
+    // Bursty traffic: a node alternates between on and off periods with the given mean lengths in
+    // cycles and only injects while on, at a higher rate so the average stays at rate
+    OnOff {
+        on_cycles: f64,
+        off_cycles: f64,
+    },
+}
+
+// Generates a synthetic workload for a grid
+// - Everything is drawn from a seeded generator, the same seed gives the same packets
+pub struct TrafficGenerator {
+    pattern: TrafficPattern,
+    process: InjectionProcess,
+    rate: f64,
+    node_rates: HashMap<Coord, f64>,
+    flits: usize,
+    rng: ChaCha8Rng,
+    // Whether every node is in an on period, drawn on the first cycle
+    bursting: Vec<Option<bool>>,
+}
+
+impl TrafficGenerator {
+    // rate is the injection rate of every node in packets per cycle
+    pub fn new(pattern: TrafficPattern, process: InjectionProcess, rate: f64, seed: u64) -> Self {
+        Self {
+            pattern,
+            process,
+            rate,
+            node_rates: HashMap::new(),
+            flits: 1,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            bursting: Vec::new(),
+        }
+    }
+
+    // Overrides the injection rate of a single node
+    pub fn set_node_rate(&mut self, pos: impl Into<Coord>, rate: f64) {
+        self.node_rates.insert(pos.into(), rate);
+    }
+
+    // Length in flits of every generated packet
+    pub fn set_packet_flits(&mut self, flits: usize) {
+        self.flits = flits.max(1);
+    }
+
+    pub fn destination(&mut self, src: Coord, dims: (Dim, Dim, Dim)) -> Coord {
+        self.pattern.destination(src, dims, &mut self.rng)
+    }
+
+    // Packets every node of a grid of the given dimensions injects on one cycle, in node order
+    pub fn tick(&mut self, dims: (Dim, Dim, Dim)) -> Vec<Packet> {
+        let (width, height, depth) = dims;
+        let count = width as usize * height as usize * depth as usize;
+        self.bursting.resize(count, None);
+
+        let mut packets = Vec::new();
+        for node in 0..count {
+            let src = Coord::new(
+                (node % width as usize) as Dim,
+                (node / width as usize % height as usize) as Dim,
+                (node / (width as usize * height as usize)) as Dim,
+            );
+            let rate = self.node_rates.get(&src).copied().unwrap_or(self.rate);
+
+            for _ in 0..self.injections(node, rate) {
+                let dest = self.destination(src, dims);
+                if dest != src {
+                    packets
+                        .push(Packet::new(PacketData::Default, src, dest).with_flits(self.flits));
+                }
+            }
+        }
+
+        packets
+    }
+
+    // Queues everything the grid's nodes inject over cycles, returns how many packets that was
+    // - Fails before injecting anything if the pattern doesn't fit the grid
+    pub fn inject(&mut self, grid: &mut Grid, cycles: Range<Cycle>) -> Result<usize, TrafficError> {
+        let dims = grid.dimensions();
+        self.pattern.check(dims)?;
+        let mut injected = 0;
+
+        for cycle in cycles {
+            for packet in self.tick(dims) {
+                grid.send_packet_at(packet, cycle)?;
+                injected += 1;
+            }
+        }
+
+        Ok(injected)
+    }
+
+    fn injections(&mut self, node: usize, rate: f64) -> usize {
+        let rate = rate.max(0.0);
+
+        match self.process {
+            InjectionProcess::Bernoulli => usize::from(self.rng.random_bool(rate.min(1.0))),
+            InjectionProcess::Poisson => {
+                // Knuth's method, fine for the small rates a network can sustain
+                let limit = (-rate).exp();
+                let mut count = 0;
+                let mut product: f64 = self.rng.random();
+                while product > limit {
+                    count += 1;
+                    product *= self.rng.random::<f64>();
+                }
+                count
+            }
+            InjectionProcess::OnOff {
+                on_cycles,
+                off_cycles,
+            } => {
+                let (on_cycles, off_cycles) = (on_cycles.max(1.0), off_cycles.max(1.0));
+                let duty = on_cycles / (on_cycles + off_cycles);
+
+                // Starts in the long run state, then flips at the end of each period
+                let on = match self.bursting[node] {
+                    None => self.rng.random_bool(duty),
+                    Some(true) => !self.rng.random_bool(1.0 / on_cycles),
+                    Some(false) => self.rng.random_bool(1.0 / off_cycles),
+                };
+                self.bursting[node] = Some(on);
+
+                usize::from(on && self.rng.random_bool((rate / duty).min(1.0)))
+            }
+        }
+    }
+}
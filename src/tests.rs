@@ -0,0 +1,1597 @@
+use std::sync::Arc;
+
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    BufferId, BufferKind, Config, ConfigError, ConfigFormat, Coord, Cycle, Dim, Direction, Event,
+    Graph, Grid, GridAccessError, GridBuilder, Heatmap, InjectionProcess, LinkParams, Metric,
+    NegativeFirst, Network, NodeCommError, NodeParams, NorthLast, OddEven, Packet, PacketData,
+    RouterPipeline, RoutingAlgorithm, Stats, Sweep, Topology, TopologyKind, TraceFormat,
+    TraceWriter, TrafficError, TrafficGenerator, TrafficPattern, VcSelection, VirtualChannels,
+    WestFirst, open_trace, read_trace, write_chrome_trace, write_vcd,
+};
+#[cfg(feature = "tui")]
+use crate::{Control, Dashboard};
+
+fn send_packet(grid: &mut Grid, packet: Packet) {
+    grid.send_packet_grid(packet)
+        .expect("Failed to send packet");
+}
+
+// Builds a grid of dims with setup applied before it's initialized, sends packets and runs it
+// until it's done, returns the grid with every event of the run in order
+fn run_grid(
+    dims: (Dim, Dim, Dim),
+    setup: impl FnOnce(&mut Grid),
+    packets: impl IntoIterator<Item = Packet>,
+) -> Result<(Grid, Vec<Event>), GridAccessError> {
+    let mut grid: Grid = Grid::default();
+    setup(&mut grid);
+    let (width, height, depth) = dims;
+    let mut event_rx = grid.init_grid_3d(width, height, depth)?;
+
+    for packet in packets {
+        send_packet(&mut grid, packet);
+    }
+    grid.run().expect("Simulation failed");
+
+    let mut events = Vec::new();
+    while let Ok(event) = event_rx.try_recv() {
+        events.push(event);
+    }
+    Ok((grid, events))
+}
+
+#[tokio::test]
+async fn small_packet_load() -> Result<(), GridAccessError> {
+    let mut grid: Grid = Grid::default();
+    let mut event_rx = grid.init_grid(5, 5)?;
+
+    let (src_1, dest_1) = ((4, 3), (1, 0));
+    let (src_2, dest_2) = ((0, 0), (4, 4));
+    let (src_3, dest_3) = ((1, 3), (4, 0));
+    let (src_4, dest_4) = ((0, 0), (1, 1));
+    let packet1 = Packet::new(PacketData::Integer(0), src_1, dest_1);
+    let packet2 = Packet::new(PacketData::Integer(0), src_2, dest_2);
+    let packet3 = Packet::new(PacketData::Integer(0), src_3, dest_3);
+    let packet4 = Packet::new(PacketData::Integer(0), src_4, dest_4);
+
+    send_packet(&mut grid, packet1);
+    send_packet(&mut grid, packet2);
+    send_packet(&mut grid, packet3);
+    send_packet(&mut grid, packet4);
+    grid.run().expect("Simulation failed");
+
+    // The run is synchronous, only waiting on arrivals that never come can hang
+    let mut expected_arrived = 4;
+    let test_result = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        while expected_arrived > 0 {
+            // Only care about when packets arrive at their final destination
+            if let Some(Event::PacketArrived { id, at, dest, .. }) = event_rx.recv().await {
+                assert_eq!(
+                    at, dest,
+                    "Packet id {id} arrived at {:?} but should've arrived at {:?}",
+                    at, dest
+                );
+                expected_arrived -= 1;
+            }
+        }
+    })
+    .await;
+
+    assert!(test_result.is_ok(), "Packet test timed out!");
+    Ok(())
+}
+
+#[tokio::test]
+async fn same_path_load() -> Result<(), GridAccessError> {
+    let mut grid: Grid = Grid::default();
+    let mut event_rx = grid.init_grid(5, 5)?;
+
+    let (src, dest) = ((0, 0), (4, 4));
+    let mut expected_arrived = 10000;
+    for _ in 0..expected_arrived {
+        let packet = Packet::new(PacketData::Integer(0), src, dest);
+        send_packet(&mut grid, packet);
+    }
+    grid.run().expect("Simulation failed");
+
+    // Same as above, the timeout only covers waiting on the arrivals
+    let test_result = tokio::time::timeout(std::time::Duration::from_secs(20), async {
+        while expected_arrived > 0 {
+            // Only care about when packets arrive at their final destination
+            if let Some(Event::PacketArrived { id, at, dest, .. }) = event_rx.recv().await {
+                assert_eq!(
+                    at, dest,
+                    "Packet id {id} arrived at {:?} but should've arrived at {:?}",
+                    at, dest
+                );
+                expected_arrived -= 1;
+            }
+        }
+    })
+    .await;
+
+    assert!(test_result.is_ok(), "Packet test timed out!");
+    Ok(())
+}
+
+#[test]
+fn deterministic_replay() -> Result<(), GridAccessError> {
+    fn record_run() -> Result<Vec<Event>, GridAccessError> {
+        let packets = [((4, 3), (1, 0)), ((0, 0), (4, 4)), ((1, 3), (4, 0))]
+            .into_iter()
+            .flat_map(|(src, dest)| {
+                (0..50).map(move |_| Packet::new(PacketData::Integer(0), src, dest))
+            });
+        Ok(run_grid((5, 5, 1), |_| {}, packets)?.1)
+    }
+
+    let first = record_run()?;
+    let second = record_run()?;
+
+    assert!(!first.is_empty(), "No events were recorded");
+    assert!(
+        first
+            .windows(2)
+            .all(|pair| pair[0].cycle() <= pair[1].cycle()),
+        "Events should come out in cycle order"
+    );
+    assert_eq!(
+        first, second,
+        "Two identical runs produced different events"
+    );
+    Ok(())
+}
+
+#[test]
+fn router_pipeline_timing() -> Result<(), GridAccessError> {
+    let pipeline = RouterPipeline {
+        buffer_write: 1,
+        route_compute: 2,
+        vc_allocation: 1,
+        switch_allocation: 1,
+        switch_traversal: 1,
+    };
+    let setup = |grid: &mut Grid| {
+        grid.set_router_pipeline(Some(pipeline));
+        grid.set_link_params(LinkParams {
+            latency: 3,
+            ..LinkParams::default()
+        });
+    };
+    let packet = Packet::new(PacketData::Integer(0), (0, 0), (3, 0));
+    let (_, events) = run_grid((5, 5, 1), setup, [packet])?;
+
+    let mut routed = 0;
+    let mut arrived_cycle = None;
+    for event in events {
+        match event {
+            Event::PacketRouted { stages, .. } => {
+                assert_eq!(stages.len(), 6);
+                assert!(stages.windows(2).all(|pair| pair[0].end == pair[1].start));
+                routed += 1;
+            }
+            Event::PacketArrived { cycle, .. } => arrived_cycle = Some(cycle),
+            _ => {}
+        }
+    }
+
+    // Uncontended, every hop takes the sum of the stage latencies
+    assert_eq!(routed, 3);
+    assert_eq!(arrived_cycle, Some(3 * 9));
+    Ok(())
+}
+
+#[test]
+fn torus_wrap_load() -> Result<(), GridAccessError> {
+    // These two are a single hop over a wrap link
+    let wrapped = [((0, 0), (4, 0)), ((2, 4), (2, 0))];
+    let others = [((4, 3), (1, 0)), ((1, 3), (4, 0)), ((0, 0), (1, 1))];
+    let packets = wrapped
+        .into_iter()
+        .chain(others)
+        .map(|(src, dest)| Packet::new(PacketData::Integer(0), src, dest));
+    let (_, events) = run_grid(
+        (5, 5, 1),
+        |grid| grid.set_topology(TopologyKind::Torus),
+        packets,
+    )?;
+
+    let mut hops = [0; 5];
+    let mut arrived = 0;
+    for event in events {
+        match event {
+            Event::PacketSent { id, .. } => hops[id] += 1,
+            Event::PacketArrived { id, at, dest, .. } => {
+                assert_eq!(
+                    at, dest,
+                    "Packet id {id} arrived at {:?} but should've arrived at {:?}",
+                    at, dest
+                );
+                arrived += 1;
+            }
+            _ => {}
+        }
+    }
+
+    assert_eq!(arrived, 5);
+    assert_eq!(hops, [1, 1, 4, 4, 2]);
+
+    // A single row only wraps along the row, no node gets a link to itself
+    let mut ring: Grid = Grid::default();
+    ring.set_topology(TopologyKind::Torus);
+    let _event_rx = ring.init_grid(5, 1)?;
+    let links = ring.links();
+    assert_eq!(links.len(), 2 * 5);
+    assert!(links.iter().all(|link| link.src != link.dest));
+    Ok(())
+}
+
+#[test]
+fn stacked_mesh_vertical_links() -> Result<(), GridAccessError> {
+    let setup = |grid: &mut Grid| {
+        grid.set_vertical_link_params(LinkParams {
+            latency: 4,
+            ..LinkParams::default()
+        })
+    };
+    let packet = Packet::new(PacketData::Integer(0), (0, 0, 0), (1, 1, 2));
+    let (_, events) = run_grid((3, 3, 3), setup, [packet])?;
+
+    let mut sent_dirs = Vec::new();
+    let mut arrived_cycle = None;
+    for event in events {
+        match event {
+            Event::PacketSent { send_dir, .. } => sent_dirs.push(send_dir),
+            Event::PacketArrived {
+                at, dest, cycle, ..
+            } => {
+                assert_eq!(at, dest);
+                arrived_cycle = Some(cycle);
+            }
+            _ => {}
+        }
+    }
+
+    // x, then y, then z and the two vertical hops take the slower TSV latency
+    assert_eq!(
+        sent_dirs,
+        [
+            Direction::Right,
+            Direction::Down,
+            Direction::Above,
+            Direction::Above
+        ]
+    );
+    assert_eq!(arrived_cycle, Some(1 + 1 + 4 + 4));
+    Ok(())
+}
+
+#[test]
+fn graph_ring_and_tree() -> Result<(), GridAccessError> {
+    // Ring of 8, going backwards from 0 to 6 is the short way round
+    let ring = Graph::ring(8, LinkParams::default());
+    let (mut network, mut event_rx) = Network::new(&ring, None);
+    for (src, dest) in [(0, 6), (1, 4), (7, 0)] {
+        let packet = Packet::new(PacketData::Integer(0), (src, 0), (dest, 0));
+        network
+            .send_packet(&ring, packet)
+            .expect("Failed to send packet");
+    }
+    network.run().expect("Simulation failed");
+
+    let mut hops = [0; 3];
+    let mut arrived = 0;
+    while let Ok(event) = event_rx.try_recv() {
+        match event {
+            Event::PacketSent { id, send_dir, .. } => {
+                hops[id] += 1;
+                if id == 0 {
+                    assert_eq!(send_dir, Direction::Port(1));
+                }
+            }
+            Event::PacketArrived { at, dest, .. } => {
+                assert_eq!(at, dest);
+                arrived += 1;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!((hops, arrived), ([2, 3, 1], 3));
+
+    // Irregular tree, leaves 3 and 4 hang off 1 and leaf 2 off the root
+    let mut tree = Graph::new(5);
+    for (parent, child) in [(0, 1), (0, 2), (1, 3), (1, 4)] {
+        tree.connect(parent, child, LinkParams::default());
+    }
+    let (mut network, mut event_rx) = Network::new(&tree, None);
+    let packet = Packet::new(PacketData::Integer(0), (3, 0), (2, 0));
+    network
+        .send_packet(&tree, packet)
+        .expect("Failed to send packet");
+    network.run().expect("Simulation failed");
+
+    let mut visited = Vec::new();
+    while let Ok(event) = event_rx.try_recv() {
+        if let Event::PacketSent { from, .. } = event {
+            visited.push(from.x);
+        }
+    }
+    assert_eq!(visited, [3, 1, 0]);
+
+    // A neighbour past the last node is an error, not a panic
+    let dangling = Graph::from_adjacency(&[vec![1], vec![0, 2]], LinkParams::default());
+    assert!(matches!(dangling, Err(GridAccessError::InvalidNodeId(2))));
+    #[cfg(not(feature = "wide-coords"))]
+    {
+        let nodes = vec![Vec::new(); Dim::MAX as usize + 1];
+        let too_many = Graph::from_adjacency(&nodes, LinkParams::default());
+        assert!(matches!(too_many, Err(GridAccessError::TooManyNodes(_))));
+    }
+
+    // Parallel links of a ring of 2 each come in on a port of their own
+    let pair = Graph::ring(2, LinkParams::default());
+    let ends: Vec<_> = pair
+        .links()
+        .iter()
+        .map(|link| (link.src, link.src_port, link.dest, link.dest_port))
+        .collect();
+    assert_eq!(
+        ends,
+        [(0, 0, 1, 0), (0, 1, 1, 1), (1, 0, 0, 0), (1, 1, 0, 1)]
+    );
+    Ok(())
+}
+
+#[test]
+fn wide_grid_coordinates() -> Result<(), GridAccessError> {
+    // Would have wrapped around with 8 bit coordinates
+    let packet = Packet::new(PacketData::Integer(0), (1000, 0), (3, 1));
+    let (grid, events) = run_grid((1024, 2, 1), |_| {}, [packet])?;
+
+    let mut hops = 0;
+    let mut arrived = false;
+    for event in events {
+        match event {
+            Event::PacketSent { .. } => hops += 1,
+            Event::PacketArrived { at, dest, .. } => {
+                assert_eq!(at, dest);
+                arrived = true;
+            }
+            _ => {}
+        }
+    }
+
+    assert!(arrived, "Packet never arrived");
+    assert_eq!(hops, 997 + 1);
+    assert!(grid.access_node((1023, 1)).is_ok());
+    assert!(grid.access_node((1024, 0)).is_err());
+    Ok(())
+}
+
+#[test]
+fn adaptive_turn_models() -> Result<(), GridAccessError> {
+    type Pair = ((Dim, Dim), (Dim, Dim));
+
+    // Runs the load and returns the directions every packet left through, in order
+    fn record_paths(
+        routing: impl RoutingAlgorithm + 'static,
+        pairs: &[Pair],
+    ) -> Result<Vec<Vec<Direction>>, GridAccessError> {
+        let packets = pairs.iter().flat_map(|&(src, dest)| {
+            (0..20).map(move |_| Packet::new(PacketData::Integer(0), src, dest))
+        });
+        let (_, events) = run_grid((6, 6, 1), |grid| grid.set_routing(routing), packets)?;
+
+        let mut paths = vec![Vec::new(); pairs.len() * 20];
+        let mut arrived = 0;
+        for event in events {
+            match event {
+                Event::PacketSent { id, send_dir, .. } => paths[id].push(send_dir),
+                Event::PacketArrived { at, dest, .. } => {
+                    assert_eq!(at, dest);
+                    arrived += 1;
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(arrived, paths.len(), "Not every packet arrived");
+        Ok(paths)
+    }
+
+    let pairs: [Pair; 6] = [
+        ((0, 0), (5, 5)),
+        ((5, 5), (0, 0)),
+        ((0, 5), (5, 0)),
+        ((5, 0), (0, 5)),
+        ((1, 4), (4, 2)),
+        ((4, 1), (2, 3)),
+    ];
+    let src_of = |id: usize| Coord::from(pairs[id / 20].0);
+    let minimal = |id: usize, path: &[Direction]| {
+        let (src, dest) = pairs[id / 20];
+        let hops = src.0.abs_diff(dest.0) + src.1.abs_diff(dest.1);
+        assert_eq!(path.len(), hops as usize, "Packet {id} took a detour");
+    };
+    let is_negative = |dir: &Direction| matches!(dir, Direction::Left | Direction::Down);
+
+    for (id, path) in record_paths(WestFirst, &pairs)?.iter().enumerate() {
+        minimal(id, path);
+        let west = path
+            .iter()
+            .take_while(|dir| **dir == Direction::Left)
+            .count();
+        assert!(!path[west..].contains(&Direction::Left), "{path:?}");
+    }
+
+    for (id, path) in record_paths(NorthLast, &pairs)?.iter().enumerate() {
+        minimal(id, path);
+        let rest = path
+            .iter()
+            .rev()
+            .take_while(|dir| **dir == Direction::Up)
+            .count();
+        assert!(
+            !path[..path.len() - rest].contains(&Direction::Up),
+            "{path:?}"
+        );
+    }
+
+    let mut distinct = std::collections::HashSet::new();
+    for (id, path) in record_paths(NegativeFirst, &pairs)?.iter().enumerate() {
+        minimal(id, path);
+        let negative = path.iter().take_while(|dir| is_negative(dir)).count();
+        assert!(!path[negative..].iter().any(is_negative), "{path:?}");
+        if id / 20 == 2 {
+            distinct.insert(path.clone());
+        }
+    }
+    // (0, 5) -> (5, 0) only goes east and north so it can adapt the whole way
+    assert!(distinct.len() > 1, "Congestion never changed a route");
+
+    for (id, path) in record_paths(OddEven, &pairs)?.iter().enumerate() {
+        minimal(id, path);
+        let mut pos = src_of(id);
+        let mut prev = Direction::Init;
+        for &dir in path {
+            let vertical = matches!(dir, Direction::Up | Direction::Down);
+            let prev_vertical = matches!(prev, Direction::Up | Direction::Down);
+            if pos.x.is_multiple_of(2) {
+                assert!(!(prev == Direction::Right && vertical), "{path:?}");
+            } else {
+                assert!(!(prev_vertical && dir == Direction::Left), "{path:?}");
+            }
+
+            match dir {
+                Direction::Up => pos.y -= 1,
+                Direction::Down => pos.y += 1,
+                Direction::Left => pos.x -= 1,
+                _ => pos.x += 1,
+            }
+            prev = dir;
+        }
+    }
+
+    // A routing with nothing to offer has no path to give either
+    struct Nowhere;
+    impl RoutingAlgorithm for Nowhere {
+        fn name(&self) -> &'static str {
+            "nowhere"
+        }
+
+        fn candidates(&self, _src: Coord, _cur: Coord, _dest: Coord) -> Vec<Direction> {
+            Vec::new()
+        }
+    }
+    let mut grid: Grid = Grid::default();
+    grid.set_routing(Nowhere);
+    let _event_rx = grid.init_grid(2, 2)?;
+    assert_eq!(grid.route(0, 1), None);
+    assert_eq!(grid.route(1, 1), Some(Vec::new()));
+
+    Ok(())
+}
+
+#[test]
+fn virtual_channels() -> Result<(), GridAccessError> {
+    // Every node of a 5 node ring sends two hops to the right, which fills the ring and deadlocks
+    // it unless the wrap link moves packets onto a second channel
+    fn ring_arrivals(vcs: VirtualChannels) -> Result<(usize, bool), GridAccessError> {
+        let mut grid: Grid = Grid::default();
+        grid.set_topology(TopologyKind::Torus);
+        grid.set_virtual_channels(vcs);
+        let mut event_rx = grid.init_grid(5, 1)?;
+
+        for _ in 0..20 {
+            for x in 0..5 {
+                let packet = Packet::new(PacketData::Integer(0), (x, 0), ((x + 2) % 5, 0));
+                send_packet(&mut grid, packet);
+            }
+        }
+        let deadlocked = matches!(grid.run(), Err(NodeCommError::Deadlock(_)));
+
+        let mut arrived = 0;
+        while let Ok(event) = event_rx.try_recv() {
+            if let Event::PacketArrived { .. } = event {
+                arrived += 1;
+            }
+        }
+        Ok((arrived, deadlocked))
+    }
+
+    let (arrived, deadlocked) = ring_arrivals(VirtualChannels::default())?;
+    assert!(arrived < 100 && deadlocked);
+    let datelines = VirtualChannels {
+        count: 2,
+        selection: VcSelection::Phase { phases: 2 },
+    };
+    assert_eq!(ring_arrivals(datelines)?, (100, false));
+
+    // Message classes never share a channel
+    let setup = |grid: &mut Grid| {
+        grid.set_virtual_channels(VirtualChannels {
+            count: 4,
+            selection: VcSelection::Class { classes: 2 },
+        })
+    };
+    let packets =
+        (0..100).map(|id| Packet::new(PacketData::Integer(0), (0, 0), (3, 3)).with_class(id % 2));
+    let (_, events) = run_grid((4, 4, 1), setup, packets)?;
+
+    for event in events {
+        if let Event::PacketSent { id, vc, .. } = event {
+            assert_eq!(
+                vc / 2,
+                id % 2,
+                "Packet {id} of class {} took vc {vc}",
+                id % 2
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn wormhole_flits() -> Result<(), GridAccessError> {
+    // Returns the cycle each packet's tail arrived on, by packet id
+    fn tail_arrivals(
+        vcs: VirtualChannels,
+        packets: Vec<Packet>,
+    ) -> Result<Vec<u64>, GridAccessError> {
+        let count = packets.len();
+        let (_, events) = run_grid((5, 5, 1), |grid| grid.set_virtual_channels(vcs), packets)?;
+
+        let mut arrivals = vec![0; count];
+        for event in events {
+            if let Event::PacketArrived { id, cycle, .. } = event {
+                arrivals[id] = cycle;
+            }
+        }
+        Ok(arrivals)
+    }
+    let packet = |src, flits| Packet::new(PacketData::Integer(0), src, (4, 0)).with_flits(flits);
+
+    // The tail trails the head by one cycle per extra flit
+    let single = tail_arrivals(VirtualChannels::default(), vec![packet((0, 0), 1)])?;
+    let long = tail_arrivals(VirtualChannels::default(), vec![packet((0, 0), 8)])?;
+    assert_eq!(single, [4]);
+    assert_eq!(long, [4 + 7]);
+
+    // Two worms merging at (1, 0) can't interleave on a single channel, the one that gets the
+    // channel first holds it until its tail is through
+    let merging = || vec![packet((0, 0), 8), packet((1, 0), 8)];
+    let arrivals = tail_arrivals(VirtualChannels::default(), merging())?;
+    assert!(arrivals[0].abs_diff(arrivals[1]) >= 8, "{arrivals:?}");
+
+    // With a second channel they share the link flit by flit instead
+    let two = VirtualChannels {
+        count: 2,
+        ..Default::default()
+    };
+    let arrivals = tail_arrivals(two, merging())?;
+    assert!(arrivals[0].abs_diff(arrivals[1]) < 8, "{arrivals:?}");
+
+    Ok(())
+}
+
+#[test]
+fn credit_round_trip() -> Result<(), GridAccessError> {
+    // Streams 100 packets down a line and returns the cycle it finished on with the number of
+    // credit stalls and the cycles spent stalled
+    fn stream(credit_latency: u64) -> Result<(u64, usize, u64), GridAccessError> {
+        let setup = |grid: &mut Grid| {
+            grid.set_link_params(LinkParams {
+                credit_latency,
+                ..LinkParams::default()
+            })
+        };
+        let packets = (0..100).map(|_| Packet::new(PacketData::Integer(0), (0, 0), (4, 0)));
+        let (grid, events) = run_grid((5, 1, 1), setup, packets)?;
+
+        let (mut stalls, mut stalled) = (0, 0);
+        for event in events {
+            if let Event::CreditStall { since, cycle, .. } = event {
+                stalls += 1;
+                stalled += cycle - since;
+            }
+        }
+        Ok((grid.cycle(), stalls, stalled))
+    }
+
+    // Two slots cover the round trip of one cycle on the link and one for the credit, so the
+    // line runs at a flit per cycle
+    assert_eq!(stream(1)?, (104, 0, 0));
+
+    // Another two cycles for the credit halves the throughput
+    let (end, stalls, stalled) = stream(3)?;
+    assert_eq!(end, 204);
+    assert!(stalls > 0);
+    assert_eq!(stalled, 98);
+
+    Ok(())
+}
+
+#[test]
+fn deadlock_detection() -> Result<(), GridAccessError> {
+    // Same single channel ring as virtual_channels, with a long stream on a second row that keeps
+    // the network busy long after the ring is stuck
+    fn stuck_torus(
+        check: Option<u64>,
+    ) -> Result<(Grid, UnboundedReceiver<Event>), GridAccessError> {
+        let mut grid: Grid = Grid::default();
+        grid.set_topology(TopologyKind::Torus);
+        grid.set_deadlock_check(check);
+        let event_rx = grid.init_grid(5, 2)?;
+
+        for _ in 0..20 {
+            for x in 0..5 {
+                let packet = Packet::new(PacketData::Integer(0), (x, 0), ((x + 2) % 5, 0));
+                send_packet(&mut grid, packet);
+            }
+        }
+        for _ in 0..500 {
+            send_packet(
+                &mut grid,
+                Packet::new(PacketData::Integer(0), (0, 1), (1, 1)),
+            );
+        }
+        Ok((grid, event_rx))
+    }
+
+    let (mut grid, _event_rx) = stuck_torus(None)?;
+    let Err(NodeCommError::Deadlock(deadlock)) = grid.run() else {
+        panic!("Ring should have deadlocked");
+    };
+    assert!(
+        deadlock.cycle >= 500,
+        "Without checks it only shows once all else is done"
+    );
+
+    // Every router of the ring waits on the next one to the right
+    assert_eq!(deadlock.channels.len(), 5);
+    let mut xs: Vec<_> = deadlock
+        .channels
+        .iter()
+        .map(|channel| channel.at.x)
+        .collect();
+    xs.sort();
+    assert_eq!(xs, [0, 1, 2, 3, 4]);
+    for channel in &deadlock.channels {
+        assert_eq!(channel.at.y, 0);
+        assert_eq!(channel.waiting_on, Direction::Right);
+    }
+
+    let (mut grid, _event_rx) = stuck_torus(Some(10))?;
+    let Err(NodeCommError::Deadlock(deadlock)) = grid.run() else {
+        panic!("Ring should have deadlocked");
+    };
+    assert!(deadlock.cycle < 50, "Found on cycle {}", deadlock.cycle);
+    assert_eq!(grid.detect_deadlock(), Some(deadlock));
+
+    // Busy but never stuck, checked on every cycle
+    let mut grid: Grid = Grid::default();
+    grid.set_deadlock_check(Some(1));
+    let _event_rx = grid.init_grid(5, 5)?;
+    for (src, dest) in [((4, 3), (1, 0)), ((0, 0), (4, 4)), ((1, 3), (4, 0))] {
+        for _ in 0..100 {
+            send_packet(&mut grid, Packet::new(PacketData::Integer(0), src, dest));
+        }
+    }
+    assert!(grid.run().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn stall_without_deadlock() -> Result<(), NodeCommError> {
+    // The turn models only know grid directions, the ports of a graph are numbered so a packet
+    // routed hop by hop has no way out of its source
+    let ring = Graph::ring(4, LinkParams::default());
+    let (mut network, _event_rx) = Network::new(&ring, None);
+    network.set_routing(Some(Arc::new(NegativeFirst)));
+    network.set_deadlock_check(Some(1));
+    let packet = Packet::new(PacketData::Integer(0), (0, 0), (2, 0));
+    network.inject(0, packet, Vec::new());
+
+    let Err(NodeCommError::Stalled { nodes, .. }) = network.run() else {
+        panic!("Packet should have been stuck at its source");
+    };
+    assert_eq!(nodes, 1);
+    assert_eq!(network.detect_deadlock(), None);
+
+    // Without a path or routing the packet has nowhere to go at all
+    let (mut network, _event_rx) = Network::new(&ring, None);
+    let packet = Packet::new(PacketData::Integer(0), (0, 0), (2, 0));
+    network.inject(0, packet, Vec::new());
+    assert!(matches!(
+        network.run(),
+        Err(NodeCommError::NoRoute { src, dest }) if (src, dest) == ((0, 0).into(), (2, 0).into())
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn synthetic_traffic() -> Result<(), TrafficError> {
+    let dims = (4, 4, 1);
+    let dest = |pattern: TrafficPattern, src: (Dim, Dim)| {
+        TrafficGenerator::new(pattern, InjectionProcess::Bernoulli, 0.0, 0)
+            .destination(src.into(), dims)
+    };
+    assert_eq!(dest(TrafficPattern::Transpose, (1, 2)), Coord::from((2, 1)));
+    assert_eq!(
+        dest(TrafficPattern::BitComplement, (0, 1)),
+        Coord::from((3, 2))
+    );
+    // Node 1 is 0001, reversed 1000 is node 8
+    assert_eq!(
+        dest(TrafficPattern::BitReverse, (1, 0)),
+        Coord::from((0, 2))
+    );
+    // Node 9 is 1001, rotated 0011 is node 3
+    assert_eq!(dest(TrafficPattern::Shuffle, (1, 2)), Coord::from((3, 0)));
+    assert_eq!(dest(TrafficPattern::Tornado, (3, 0)), Coord::from((0, 1)));
+    assert_eq!(dest(TrafficPattern::Neighbor, (3, 3)), Coord::from((0, 0)));
+    // Big enough a side that the shift overflows a Dim
+    let mut tornado =
+        TrafficGenerator::new(TrafficPattern::Tornado, InjectionProcess::Bernoulli, 0.0, 0);
+    assert_eq!(
+        tornado.destination((59999, 0).into(), (60000, 1, 1)),
+        Coord::from((29998, 0))
+    );
+    let hotspot = TrafficPattern::Hotspot {
+        hotspots: vec![(2, 2).into()],
+        fraction: 1.0,
+    };
+    assert_eq!(dest(hotspot, (0, 0)), Coord::from((2, 2)));
+
+    let mut uniform =
+        TrafficGenerator::new(TrafficPattern::Uniform, InjectionProcess::Bernoulli, 0.0, 1);
+    for _ in 0..200 {
+        assert_ne!(
+            uniform.destination((1, 1).into(), dims),
+            Coord::from((1, 1))
+        );
+    }
+
+    // Every process averages out at the requested rate, 0.1 * 16 nodes * 2000 cycles
+    for process in [
+        InjectionProcess::Bernoulli,
+        InjectionProcess::Poisson,
+        InjectionProcess::OnOff {
+            on_cycles: 20.0,
+            off_cycles: 60.0,
+        },
+    ] {
+        let mut generator = TrafficGenerator::new(TrafficPattern::Uniform, process, 0.1, 7);
+        let count: usize = (0..2000).map(|_| generator.tick(dims).len()).sum();
+        assert!(
+            (2800..3600).contains(&count),
+            "{process:?} injected {count}"
+        );
+    }
+
+    // Per node rates, only (0, 0) talks
+    let mut generator =
+        TrafficGenerator::new(TrafficPattern::Uniform, InjectionProcess::Bernoulli, 0.0, 7);
+    generator.set_node_rate((0, 0), 1.0);
+    let packets = generator.tick(dims);
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].header.src_pos, Coord::from((0, 0)));
+
+    // Same seed, same workload, and all of it arrives
+    let run = || -> Result<(usize, Vec<Event>), TrafficError> {
+        let mut grid: Grid = Grid::default();
+        let mut event_rx = grid.init_grid(4, 4).map_err(NodeCommError::from)?;
+        let mut generator = TrafficGenerator::new(
+            TrafficPattern::Transpose,
+            InjectionProcess::Poisson,
+            0.05,
+            3,
+        );
+        generator.set_packet_flits(2);
+        let injected = generator.inject(&mut grid, 0..200)?;
+        grid.run()?;
+
+        let mut events = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            events.push(event);
+        }
+        Ok((injected, events))
+    };
+    let (injected, events) = run()?;
+    assert!(injected > 0);
+    let arrived = events
+        .iter()
+        .filter(|event| matches!(event, Event::PacketArrived { .. }))
+        .count();
+    assert_eq!(arrived, injected);
+    assert_eq!(run()?.1, events);
+
+    // No transpose of a grid that isn't square
+    let mut grid: Grid = Grid::default();
+    let _event_rx = grid.init_grid(4, 3).map_err(NodeCommError::from)?;
+    let mut transpose = TrafficGenerator::new(
+        TrafficPattern::Transpose,
+        InjectionProcess::Bernoulli,
+        1.0,
+        0,
+    );
+    assert!(matches!(
+        transpose.inject(&mut grid, 0..10),
+        Err(TrafficError::NotSquare {
+            width: 4,
+            height: 3
+        })
+    ));
+    assert!(grid.is_idle());
+
+    Ok(())
+}
+
+#[test]
+fn latency_statistics() -> Result<(), NodeCommError> {
+    // Ten packets queued at once behind each other and one that stays at its source
+    let packets = (0..10)
+        .map(|_| Packet::new(PacketData::Integer(0), (0, 0), (4, 4)))
+        .chain([Packet::new(PacketData::Integer(0), (2, 2), (2, 2))]);
+    let (_, events) = run_grid((5, 5, 1), |_| {}, packets)?;
+
+    let mut stats = Stats::new();
+    events.iter().for_each(|event| stats.record(event));
+
+    assert_eq!(stats.packets_arrived(), 11);
+    assert_eq!(stats.in_network(), 0);
+    assert_eq!(stats.hops().min(), Some(0));
+    assert_eq!(stats.hops().max(), Some(8));
+
+    // Only the queue at the source grows, every packet spends the same time in the network
+    let pair = stats.pair((0, 0).into(), (4, 4).into()).unwrap();
+    assert_eq!(pair.latency.count(), 10);
+    assert_eq!(pair.hops.mean(), Some(8.0));
+    assert_eq!(stats.network_latency().min(), Some(0));
+    assert_eq!(stats.network_latency().percentile(10.0), Some(8));
+    assert_eq!(stats.network_latency().max(), Some(8));
+    assert_eq!(stats.queuing_delay().max(), Some(9));
+    assert_eq!(stats.latency().max(), Some(17));
+    assert_eq!(stats.latency().percentile(50.0), Some(12));
+
+    // Everything arrived within the 18 cycles at (4, 4) but one
+    assert_eq!(stats.cycles(), 18);
+    assert_eq!(stats.throughput(), 11.0 / 18.0);
+    assert_eq!(stats.node_throughput()[&Coord::from((4, 4))], 10.0 / 18.0);
+
+    Ok(())
+}
+
+#[test]
+fn load_latency_sweep() -> Result<(), TrafficError> {
+    let rates = (1..=10).map(|step| step as f64 / 10.0).collect();
+    let mut sweep = Sweep::new((4, 4, 1), TrafficPattern::Uniform, rates);
+    (sweep.warmup, sweep.measure, sweep.drain) = (200, 400, 800);
+
+    let mut grid = Grid::default();
+    let curve = sweep.run(&mut grid)?;
+
+    // Latency stays close to zero load until the mesh runs out of bandwidth, accepted load
+    // follows offered load until then
+    let (last, below) = curve.points.split_last().unwrap();
+    assert!(last.saturated && last.unfinished > 0);
+    assert_eq!(curve.saturation, Some(below.last().unwrap().rate));
+    assert!(
+        (0.4..0.9).contains(&last.rate),
+        "Saturated at {}",
+        last.rate
+    );
+    for pair in below.windows(2) {
+        assert!(pair[0].latency <= pair[1].latency);
+    }
+    for point in below {
+        assert!(!point.saturated);
+        assert!((point.accepted - point.offered).abs() < 0.02);
+    }
+
+    let mut csv = Vec::new();
+    curve.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), curve.points.len() + 1);
+    assert!(lines[0].starts_with("rate,offered,accepted,latency"));
+    assert!(lines[1].starts_with("0.1,"));
+    assert!(lines.last().unwrap().ends_with(",true"));
+
+    Ok(())
+}
+
+#[test]
+fn sweep_from_zero_load() -> Result<(), TrafficError> {
+    // Nothing is injected at rate 0, the latencies of the next rates are compared against the
+    // first one packets arrived at instead
+    let mut sweep = Sweep::new(
+        (4, 4, 1),
+        TrafficPattern::Uniform,
+        vec![0.0, 0.05, 0.1, 0.15],
+    );
+    (sweep.warmup, sweep.measure, sweep.drain) = (200, 400, 800);
+
+    let curve = sweep.run(&mut Grid::default())?;
+    assert_eq!(curve.points.len(), 4);
+    assert_eq!((curve.points[0].packets, curve.points[0].latency), (0, 0.0));
+    assert!(curve.points.iter().all(|point| !point.saturated));
+    assert_eq!(curve.saturation, None);
+
+    // Every measured packet of a full rate can't make it through, so that rate saturates and
+    // there's nothing below it
+    sweep.rates = vec![1.0];
+    let curve = sweep.run(&mut Grid::default())?;
+    assert_eq!(curve.points.len(), 1);
+    assert!(curve.points[0].saturated);
+    assert_eq!(curve.saturation, None);
+
+    Ok(())
+}
+
+#[test]
+fn link_and_buffer_monitoring() -> Result<(), NodeCommError> {
+    // Ten two flit packets straight along the top row
+    let packets =
+        (0..10).map(|_| Packet::new(PacketData::Integer(0), (0, 0), (4, 0)).with_flits(2));
+    let (grid, _) = run_grid((5, 5, 1), |grid| grid.set_sample_interval(Some(1)), packets)?;
+    let end = grid.cycle();
+
+    let usage = grid.link_usage();
+    assert_eq!(usage.len(), 2 * (5 * 4 + 4 * 5));
+    for link in &usage {
+        if link.dir == Direction::Right && link.from.y == 0 {
+            assert_eq!(link.to, Coord::new(link.from.x + 1, 0, 0));
+            assert_eq!(link.counters.flits, 20);
+            assert_eq!(link.counters.packets, 10);
+            assert_eq!(link.counters.busy_cycles, 20);
+            assert_eq!(link.utilization(end), 20.0 / end as f64);
+        } else {
+            assert_eq!(link.counters.flits, 0, "{link:?}");
+        }
+    }
+
+    let series = grid.time_series();
+    let local: Vec<_> = series
+        .buffer(BufferId {
+            at: Coord::new(0, 0, 0),
+            dir: Direction::Init,
+            kind: BufferKind::Local,
+        })
+        .unwrap()
+        .collect();
+    // Sampled on every cycle up to the last one, the queue empties a flit a cycle
+    assert_eq!(local.len(), end as usize + 1);
+    assert_eq!(&local[..3], [(0, 0), (1, 19), (2, 18)]);
+    assert_eq!(local[20..], [(20, 0), (21, 0), (22, 0), (23, 0), (24, 0)]);
+
+    let mut csv = Vec::new();
+    series.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.starts_with("cycle,x,y,z,dir,series,value\n0,0,0,0,Up,link_buffer,0\n"));
+    assert!(csv.contains(&format!("{end},0,0,0,Right,link_flits,20\n")));
+
+    Ok(())
+}
+
+#[test]
+fn congestion_heatmap() -> Result<(), NodeCommError> {
+    fn load() -> impl Iterator<Item = Packet> {
+        (0..10).flat_map(|_| {
+            [
+                Packet::new(PacketData::Integer(0), (0, 0), (2, 0)),
+                Packet::new(PacketData::Integer(0), (2, 2), (2, 1)),
+            ]
+        })
+    }
+
+    let (grid, events) = run_grid((3, 3, 1), |grid| grid.set_sample_interval(Some(1)), load())?;
+    let mut stats = Stats::new();
+    events.iter().for_each(|event| stats.record(event));
+
+    // East along the top row and one hop north in the last column, at 10 flits over 13 cycles
+    let picture = Heatmap::new(Metric::Throughput).render(&grid, &stats);
+    let lines: Vec<&str> = picture.lines().collect();
+    assert_eq!(lines[0], "@@ @  @@ @  []");
+    assert_eq!(lines[2], "[]    []    []");
+    assert_eq!(lines[3].trim_end(), "             @");
+    assert_eq!(lines[4], "[]    []    @@");
+    assert!(lines[5].starts_with("throughput (flits/cycle) on cycle 12"));
+
+    // Queues only build up at the two sources, latency is worst at the far destination
+    let picture = Heatmap::new(Metric::Occupancy).render(&grid, &stats);
+    assert!(picture.starts_with("@@    []    []\n"));
+    assert!(picture.contains("[]    []    @@\nbuffer occupancy"));
+    let picture = Heatmap::new(Metric::Latency).render(&grid, &stats);
+    assert!(picture.starts_with("[]    []    @@\n"));
+    assert!(picture.contains("[]    []    %%\n"));
+
+    let mut heatmap = Heatmap::new(Metric::Throughput);
+    heatmap.set_color(true);
+    assert!(heatmap.render(&grid, &stats).contains("\x1b[31m@\x1b[0m"));
+
+    // Redrawn every 4 cycles until the grid is done
+    let mut grid: Grid = Grid::default();
+    let mut event_rx = grid.init_grid(3, 3)?;
+    load().for_each(|packet| send_packet(&mut grid, packet));
+    let mut frames = Vec::new();
+    let stats = Heatmap::new(Metric::Occupancy)
+        .live(
+            &mut grid,
+            &mut event_rx,
+            4,
+            std::time::Duration::ZERO,
+            &mut frames,
+        )
+        .expect("Live view failed");
+    let frames = String::from_utf8(frames).unwrap();
+    assert_eq!(frames.matches("\x1b[H\x1b[2J").count(), 4);
+    assert!(frames.contains("buffer occupancy (flits) on cycle 3,"));
+    assert_eq!(stats.packets_arrived(), 20);
+
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+#[test]
+fn dashboard_pause_step_resume() -> Result<(), NodeCommError> {
+    use ratatui::{Terminal, backend::TestBackend};
+
+    let mut grid: Grid = Grid::default();
+    let event_rx = grid.init_grid(3, 3)?;
+    for _ in 0..5 {
+        send_packet(
+            &mut grid,
+            Packet::new(PacketData::Integer(0), (0, 0), (2, 2)),
+        );
+    }
+
+    let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+    let mut screen = |dashboard: &Dashboard, grid: &Grid| {
+        let frame = terminal.draw(|frame| dashboard.draw(frame, grid)).unwrap();
+        frame
+            .buffer
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect::<String>()
+    };
+
+    // Nothing moves until stepped
+    let mut dashboard = Dashboard::new(event_rx);
+    assert!(dashboard.is_paused());
+    dashboard.advance(&mut grid)?;
+    assert_eq!(grid.cycle(), 0);
+
+    for _ in 0..3 {
+        dashboard.control(&mut grid, Control::Step)?;
+    }
+    assert_eq!(grid.cycle(), 2);
+    assert_eq!(dashboard.counters((0, 0)).sent, 3);
+    assert_eq!(dashboard.in_flight().len(), 3);
+    let text = screen(&dashboard, &grid);
+    assert!(text.contains("Packets in flight (3)"));
+    assert!(text.contains("cycle 2 paused"));
+    assert!(text.contains("2: packet 2 sent from (0, 0, 0) to Down on vc 0"));
+
+    // Resumed it runs to the end, stepping while running does nothing
+    dashboard.control(&mut grid, Control::Toggle)?;
+    dashboard.control(&mut grid, Control::Faster)?;
+    dashboard.control(&mut grid, Control::Step)?;
+    while !grid.is_idle() {
+        dashboard.advance(&mut grid)?;
+    }
+    assert_eq!(dashboard.counters((2, 2)).arrived, 5);
+    assert_eq!(dashboard.counters((0, 1)).received, 5);
+    assert!(dashboard.in_flight().is_empty());
+    assert_eq!(dashboard.stats().packets_arrived(), 5);
+    assert!(screen(&dashboard, &grid).contains("done at 2 cycles/frame"));
+
+    // Cycling the metric keeps the rest of the heatmap
+    dashboard.heatmap_mut().set_layer(1);
+    dashboard.control(&mut grid, Control::Metric)?;
+    assert_eq!(dashboard.heatmap_mut().metric(), Metric::Latency);
+    assert_eq!(dashboard.heatmap_mut().layer(), 1);
+
+    assert!(!dashboard.control(&mut grid, Control::Quit)?);
+    Ok(())
+}
+
+#[test]
+fn trace_round_trip() -> Result<(), NodeCommError> {
+    let packets = [((0, 0), (2, 2)), ((2, 1), (0, 1)), ((1, 1), (1, 1))]
+        .map(|(src, dest)| Packet::new(PacketData::Integer(0), src, dest));
+    let (_, events) = run_grid(
+        (3, 3, 1),
+        |grid| grid.set_router_pipeline(Some(RouterPipeline::default())),
+        packets,
+    )?;
+    // Routed events stay out of the trace
+    let traced: Vec<Event> = events
+        .iter()
+        .filter(|event| !matches!(event, Event::PacketRouted { .. }))
+        .cloned()
+        .collect();
+    assert!(traced.len() < events.len());
+
+    for format in [TraceFormat::JsonLines, TraceFormat::Csv] {
+        let mut writer = TraceWriter::new(Vec::new(), format);
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        let trace = writer.finish().unwrap();
+        assert_eq!(read_trace(trace.as_slice(), format).unwrap(), traced);
+    }
+
+    let mut writer = TraceWriter::new(Vec::new(), TraceFormat::JsonLines);
+    // The packet to itself arrives straight away, then the first hop leaves after the pipeline
+    writer.write(&traced[1]).unwrap();
+    let line = String::from_utf8(writer.finish().unwrap()).unwrap();
+    assert_eq!(
+        line,
+        "{\"event\":\"PacketSent\",\"id\":0,\"send_dir\":\"Down\",\"vc\":0,\"from\":{\"x\":0,\"y\":0,\"z\":0},\"cycle\":2}\n"
+    );
+
+    // Files pick their format from the extension
+    let dir = std::env::temp_dir().join(format!("mesh-sim-trace-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["trace.jsonl", "trace.csv"] {
+        let path = dir.join(name);
+        let mut writer = TraceWriter::create(&path).unwrap();
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(open_trace(&path).unwrap(), traced);
+    }
+    let csv = std::fs::read_to_string(dir.join("trace.csv")).unwrap();
+    assert!(csv.starts_with("cycle,event,id,x,y,z,dir,vc,"));
+    // Blank lines and a byte order mark before the header are skipped
+    let padded = format!("\u{feff}\n  \n{csv}");
+    assert_eq!(
+        read_trace(padded.as_bytes(), TraceFormat::Csv).unwrap(),
+        traced
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let broken = "cycle,event,id,x,y,z,dir,vc,src_x,src_y,src_z,dest_x,dest_y,dest_z,created,hops,flits,class\n\
+         1,PacketSent,0,0,0,0,Sideways,0,,,,,,,,,,\n";
+    let err = read_trace(broken.as_bytes(), TraceFormat::Csv).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid trace on line 2: unknown direction \"Sideways\""
+    );
+
+    Ok(())
+}
+
+#[test]
+fn chrome_trace_export() -> Result<(), NodeCommError> {
+    let packets = (0..2).map(|_| Packet::new(PacketData::Integer(0), (0, 0), (2, 1)));
+    let (_, events) = run_grid((3, 3, 1), |_| {}, packets)?;
+
+    let mut json = Vec::new();
+    write_chrome_trace(&events, &mut json).unwrap();
+    let trace: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    let trace_events = trace["traceEvents"].as_array().unwrap();
+    let phase = |ph: &str| {
+        trace_events
+            .iter()
+            .filter(|event| event["ph"] == ph)
+            .collect::<Vec<_>>()
+    };
+
+    // A track for every node on the path, in row major order
+    let names: Vec<&str> = phase("M")
+        .iter()
+        .filter(|event| event["name"] == "thread_name")
+        .map(|event| event["args"]["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "node (0, 0, 0)",
+            "node (0, 1, 0)",
+            "node (1, 1, 0)",
+            "node (2, 1, 0)"
+        ]
+    );
+
+    // A slice per node for each packet, the last one ends with its arrival
+    let slices = phase("X");
+    assert_eq!(slices.len(), 2 * 4);
+    let arrivals: Vec<_> = slices
+        .iter()
+        .filter(|slice| slice["cat"] == "arrival")
+        .collect();
+    assert_eq!(arrivals.len(), 2);
+    for arrival in arrivals {
+        assert_eq!(arrival["tid"], 3);
+        let end = arrival["ts"].as_u64().unwrap() + arrival["dur"].as_u64().unwrap();
+        assert_eq!(end, arrival["args"]["latency"].as_u64().unwrap());
+    }
+
+    // Every hop is an arrow to a later point on the next node
+    let (starts, ends) = (phase("s"), phase("f"));
+    assert_eq!(starts.len(), 2 * 3);
+    assert_eq!(ends.len(), starts.len());
+    for start in starts {
+        let end = ends.iter().find(|end| end["id"] == start["id"]).unwrap();
+        assert_ne!(start["tid"], end["tid"]);
+        assert!(start["ts"].as_u64() <= end["ts"].as_u64());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn vcd_waveform_export() -> Result<(), NodeCommError> {
+    // The packet in the other row takes id 0, so the traced id can't be mistaken for an empty
+    // link
+    let packets = [
+        Packet::new(PacketData::Integer(0), (0, 1), (1, 1)),
+        Packet::new(PacketData::Integer(0), (0, 0), (1, 0)).with_flits(3),
+    ];
+    let (grid, events) = run_grid((2, 2, 1), |grid| grid.set_sample_interval(Some(1)), packets)?;
+    let id = events
+        .iter()
+        .find_map(|event| match event {
+            Event::PacketSent { id, from, .. } if *from == Coord::new(0, 0, 0) => Some(*id),
+            _ => None,
+        })
+        .unwrap();
+    assert_ne!(id, 0);
+
+    let mut vcd = Vec::new();
+    write_vcd(grid.time_series(), &mut vcd).unwrap();
+    let vcd = String::from_utf8(vcd).unwrap();
+
+    // A valid and a packet signal per link, one per buffer and the clock
+    let series = grid.time_series();
+    let vars = vcd.lines().filter(|line| line.starts_with("$var")).count();
+    assert_eq!(vars, 1 + 2 * series.links.len() + series.buffers.len());
+    assert_eq!(series.links.len(), 8);
+
+    // Names by identifier, prefixed with the scope they are in
+    let mut names = std::collections::HashMap::new();
+    let mut scope = "";
+    for line in vcd.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["$scope", "module", name, "$end"] => scope = name,
+            ["$var", _, _, code, name, "$end"] => {
+                names.insert(code, format!("{scope}.{name}"));
+            }
+            _ => {}
+        }
+    }
+
+    // Cycles the link out of the source was busy and what it carried
+    let mut cycle = 0;
+    let mut busy = Vec::new();
+    let mut carried = Vec::new();
+    for line in vcd.lines().skip_while(|line| !line.starts_with('#')) {
+        if let Some(time) = line.strip_prefix('#') {
+            cycle = time.parse::<u64>().unwrap() / 2;
+        } else if let Some((value, code)) = line.split_once(' ') {
+            if names.get(code).map(String::as_str) == Some("node_0_0_0.right_packet") {
+                carried.push(value.to_string());
+            }
+        } else if line.len() > 1 && !line.starts_with('$') {
+            let (value, code) = line.split_at(1);
+            if names.get(code).map(String::as_str) == Some("node_0_0_0.right_valid") {
+                busy.push((cycle, value == "1"));
+            }
+        }
+    }
+    let high: Vec<u64> = busy
+        .windows(2)
+        .filter(|pair| pair[0].1)
+        .flat_map(|pair| pair[0].0..pair[1].0)
+        .collect();
+    assert_eq!(high.len(), 3);
+    assert!(high.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    assert_eq!(busy.last(), Some(&(high[2] + 1, false)));
+    assert!(carried.contains(&format!("b{id:b}")));
+
+    Ok(())
+}
+
+#[test]
+fn topology_dot_and_svg() -> Result<(), NodeCommError> {
+    let mut grid: Grid = Grid::default();
+    let _event_rx = grid.init_grid(3, 2)?;
+
+    // Before running links only carry their parameters
+    let mut dot = Vec::new();
+    grid.write_dot(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    let edges: Vec<&str> = dot.lines().filter(|line| line.contains("->")).collect();
+    assert_eq!(edges.len(), 2 * 7);
+    assert!(dot.contains("n0 [label=\"(0, 0, 0)\", pos=\"0,0!\"]"));
+    assert!(edges.iter().all(|edge| edge.contains("bw 1, buf 2\"")));
+    assert!(!dot.contains("utilization"));
+
+    send_packet(
+        &mut grid,
+        Packet::new(PacketData::Integer(0), (0, 0), (2, 0)),
+    );
+    grid.run()?;
+
+    // Only the links the packet took were busy
+    let mut dot = Vec::new();
+    grid.write_dot(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    let busy = dot
+        .lines()
+        .filter(|line| line.contains("->") && !line.contains("utilization=0.0000"))
+        .count();
+    assert_eq!(busy, 2);
+    assert!(dot.contains("n0 -> n1 [label=\"bw 1, buf 2, util"));
+
+    let mut svg = Vec::new();
+    grid.write_svg(&mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.starts_with("<svg") && svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("<line").count(), 2 * 7);
+    assert_eq!(svg.matches("<circle").count(), 6);
+    assert!(svg.contains("<title>(0, 0, 0) Right: bw 1, buf 2, util"));
+
+    Ok(())
+}
+
+#[test]
+fn config_files() -> Result<(), ConfigError> {
+    let toml = r#"
+        routing = "west_first"
+
+        [topology]
+        width = 4
+        height = 3
+
+        [links]
+        bandwidth = 2
+
+        [nodes]
+        rx_rate = 3
+        link_buffer = 4
+
+        [[nodes.overrides]]
+        at = { x = 1, y = 1, z = 0 }
+        inner_buffer = 8
+
+        [router]
+        virtual_channels = 2
+        vc_selection = { class = { classes = 2 } }
+
+        [traffic]
+        pattern = "tornado"
+        rate = 0.2
+        cycles = 50
+        seed = 3
+    "#;
+    let config = Config::parse(toml, ConfigFormat::Toml)?;
+    assert_eq!(config.topology.depth, 1);
+    assert_eq!(config.nodes.tx_rate, 5);
+
+    // The same config as JSON
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(Config::parse(&json, ConfigFormat::Json)?, config);
+
+    let (mut grid, mut event_rx) = Grid::from_config(&config)?;
+    assert_eq!(grid.dimensions(), (4, 3, 1));
+    assert_eq!(grid.routing().name(), WestFirst.name());
+    let node = grid.access_node((1, 1))?;
+    assert_eq!((node.rx_rate, node.vc_count()), (3, 2));
+    assert_eq!(node.params().inner_buffer, 8);
+    assert_eq!(grid.access_node((0, 0))?.params().inner_buffer, 4);
+
+    let traffic = config.traffic.as_ref().unwrap();
+    let injected = traffic
+        .generator()
+        .inject(&mut grid, 0..traffic.cycles)
+        .unwrap();
+    grid.run().unwrap();
+    let mut stats = Stats::new();
+    stats.drain(&mut event_rx);
+    assert!(injected > 0);
+    assert_eq!(stats.packets_arrived(), injected);
+
+    // Every error names the field it is about
+    let broken = |text: &str| Config::parse(text, ConfigFormat::Toml).unwrap_err();
+    let field = |err: ConfigError| match err {
+        ConfigError::Parse { field, .. } | ConfigError::Invalid { field, .. } => field,
+        err => panic!("unexpected error {err}"),
+    };
+    let base = "[topology]\nwidth = 4\nheight = 4\n";
+    assert_eq!(field(broken("[topology]\nwidth = 4\n")), "topology");
+    assert_eq!(
+        field(broken(&format!("{base}[links]\nlatency = \"fast\"\n"))),
+        "links.latency"
+    );
+    assert_eq!(
+        field(broken(&format!("{base}[router]\nvirtual_chanels = 2\n"))),
+        "router.virtual_chanels"
+    );
+    assert_eq!(
+        field(broken("[topology]\nwidth = 0\nheight = 4\n")),
+        "topology.width"
+    );
+    assert_eq!(
+        field(broken(&format!(
+            "{base}[[nodes.overrides]]\nat = {{ x = 4, y = 0, z = 0 }}\n"
+        ))),
+        "nodes.overrides[0].at"
+    );
+    let hotspot =
+        "pattern = { hotspot = { hotspots = [{ x = 1, y = 1, z = 0 }], fraction = 2.0 } }";
+    let err = broken(&format!("{base}[traffic]\n{hotspot}\n"));
+    assert_eq!(
+        err.to_string(),
+        "Invalid config at traffic.pattern.hotspot.fraction: must be between 0 and 1"
+    );
+    assert_eq!(
+        field(broken(
+            "[topology]\nwidth = 4\nheight = 3\n[traffic]\npattern = \"transpose\"\n"
+        )),
+        "traffic.pattern"
+    );
+    assert_eq!(
+        field(broken(
+            "routing = \"west_first\"\n[topology]\nkind = \"torus\"\nwidth = 4\nheight = 4\n"
+        )),
+        "routing"
+    );
+    assert_eq!(
+        field(broken(&format!(
+            "routing = \"odd_even\"\n{base}depth = 2\n"
+        ))),
+        "routing"
+    );
+    assert_eq!(
+        field(broken(&format!(
+            "{base}[traffic]\nnode_rates = [{{ at = {{ x = 1, y = 1, z = 0 }}, rate = 3.0 }}]\n"
+        ))),
+        "traffic.node_rates[0].rate"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn grid_builder() -> Result<(), ConfigError> {
+    let slow = LinkParams {
+        latency: 6,
+        ..LinkParams::default()
+    };
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
+    // Cycle a packet across the top row arrives on, the receiver build returns stays empty
+    let arrival = |builder: GridBuilder| -> Result<Cycle, ConfigError> {
+        let (mut grid, mut own_rx) = builder.build()?;
+        send_packet(
+            &mut grid,
+            Packet::new(PacketData::Integer(0), (0, 0), (3, 0)),
+        );
+        let cycle = grid.run().unwrap();
+        assert!(own_rx.try_recv().is_err());
+        Ok(cycle)
+    };
+
+    let builder = GridBuilder::new(4, 3)
+        .buffers(3, 6)
+        .node_rates(2, 4)
+        .node_params_at(
+            (1, 1),
+            NodeParams {
+                inner_buffer: 8,
+                ..NodeParams::default()
+            },
+        )
+        .routing(WestFirst)
+        .event_sink(event_tx);
+    let (grid, _) = builder.clone().build()?;
+    assert_eq!(grid.routing().name(), WestFirst.name());
+    let node = grid.access_node((0, 0))?;
+    assert_eq!(
+        node.params(),
+        NodeParams {
+            tx_rate: 2,
+            rx_rate: 4,
+            link_buffer: 3,
+            inner_buffer: 6,
+        }
+    );
+    assert_eq!(grid.access_node((1, 1))?.params().inner_buffer, 8);
+
+    // Only the link it names is slowed down, every event goes to the sink
+    let fast = arrival(builder.clone())?;
+    let slowed = builder.link_params_at((0, 0), Direction::Right, slow);
+    let (grid, _) = slowed.clone().build()?;
+    let right = grid.access_node((0, 0))?.out_links[Direction::Right.index()].unwrap();
+    let down = grid.access_node((0, 0))?.out_links[Direction::Down.index()].unwrap();
+    assert_eq!(
+        (right.params.latency, down.params),
+        (6, LinkParams::default())
+    );
+    assert_eq!(arrival(slowed)?, fast + 5);
+    let mut arrived = 0;
+    while let Ok(event) = event_rx.try_recv() {
+        arrived += matches!(event, Event::PacketArrived { .. }) as usize;
+    }
+    assert_eq!(arrived, 2);
+
+    // Typed errors instead of a grid that panics later
+    let field = |builder: GridBuilder| match builder.build() {
+        Err(ConfigError::Invalid { field, .. }) => field,
+        Err(err) => panic!("unexpected error {err}"),
+        Ok(_) => panic!("built an invalid grid"),
+    };
+    assert_eq!(field(GridBuilder::new(0, 4)), "width");
+    assert_eq!(
+        field(GridBuilder::new(4, 4).buffers(0, 4)),
+        "node_params.link_buffer"
+    );
+    assert_eq!(
+        field(GridBuilder::new(4, 4).node_params_at((4, 0), NodeParams::default())),
+        "node_params_at[(4, 0, 0)]"
+    );
+    assert_eq!(
+        field(GridBuilder::new(4, 4).link_params_at((3, 0), Direction::Right, slow)),
+        "link_params_at[(3, 0, 0) Right]"
+    );
+    assert_eq!(
+        field(GridBuilder::new(4, 4).virtual_channels(VirtualChannels {
+            count: 2,
+            selection: VcSelection::Class { classes: 3 },
+        })),
+        "virtual_channels.selection.classes"
+    );
+    assert_eq!(
+        field(GridBuilder::new(4, 4).sample_interval(Some(0))),
+        "sample_interval"
+    );
+    assert_eq!(
+        field(
+            GridBuilder::new(4, 4)
+                .topology(TopologyKind::Torus)
+                .routing(WestFirst)
+        ),
+        "routing"
+    );
+    assert_eq!(
+        field(GridBuilder::new(4, 4).depth(2).routing(OddEven)),
+        "routing"
+    );
+    // A torus has the wrap link the mesh lacks
+    GridBuilder::new(4, 4)
+        .topology(TopologyKind::Torus)
+        .link_params_at((3, 0), Direction::Right, slow)
+        .build()?;
+
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::arch::coord::{Coord, Dim};
+use crate::arch::grid::Grid;
+use crate::comm::packet::Event;
+use crate::comm::transfer::{Direction, NodeCommError};
+use crate::sim::engine::Cycle;
+use crate::sim::monitor::{BufferId, BufferKind};
+use crate::sim::stats::Stats;
+
+// Lightest to heaviest, nothing at all is drawn as a blank
+const SHADES: [char; 9] = ['.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("{0}")]
+    Simulation(#[from] NodeCommError),
+    #[error("Unable to draw to the terminal: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum Metric {
+    // Flits per cycle, leaving each node and crossing each link
+    #[default]
+    Throughput,
+    // Flits buffered in each node and in the link buffer at the far end of each link, averaged
+    // over the samples taken so far or the current contents if there are none
+    Occupancy,
+    // Mean latency of the packets that arrived at each node, links aren't shaded
+    Latency,
+}
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Throughput => "throughput (flits/cycle)",
+            Metric::Occupancy => "buffer occupancy (flits)",
+            Metric::Latency => "latency (cycles)",
+        }
+    }
+}
+
+// Draws one layer of a grid as characters, every node and link shaded by the metric relative to
+// the busiest one
+// - Nodes are two characters wide and drawn as [] while idle, the pair between two nodes is the
+// east then west link and the pair under a node its south then north link
+// - Wrap links of a torus and the vertical links of a stacked mesh aren't drawn
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Heatmap {
+    metric: Metric,
+    color: bool,
+    layer: Dim,
+}
+
+// Metric of every node and of every link by the node and direction it leaves through
+#[derive(Default)]
+struct Values {
+    nodes: HashMap<Coord, f64>,
+    links: HashMap<(Coord, Direction), f64>,
+}
+
+impl Heatmap {
+    pub fn new(metric: Metric) -> Self {
+        Self {
+            metric,
+            ..Self::default()
+        }
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    // Switches the metric, colours and layer stay as they were
+    pub fn set_metric(&mut self, metric: Metric) {
+        self.metric = metric;
+    }
+
+    // Colours the shades green to red with ANSI escapes
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    pub fn set_layer(&mut self, layer: Dim) {
+        self.layer = layer;
+    }
+
+    pub fn layer(&self) -> Dim {
+        self.layer
+    }
+
+    // Summary of the run so far, stats has to have seen its events for the latency metric
+    pub fn render(&self, grid: &Grid, stats: &Stats) -> String {
+        self.draw(grid, &self.values(grid, stats, false))
+    }
+
+    // Same as render but with what the buffers hold right now
+    pub fn render_current(&self, grid: &Grid, stats: &Stats) -> String {
+        self.draw(grid, &self.values(grid, stats, true))
+    }
+
+    // Runs the grid to the end, redrawing every refresh cycles with the current buffer contents
+    // - delay is waited between frames so the picture can be followed
+    // - Returns the statistics of everything the grid sent on event_rx
+    pub fn live(
+        &self,
+        grid: &mut Grid,
+        event_rx: &mut UnboundedReceiver<Event>,
+        refresh: Cycle,
+        delay: Duration,
+        mut out: impl io::Write,
+    ) -> Result<Stats, RenderError> {
+        let mut stats = Stats::new();
+        let mut end = grid.cycle();
+
+        while !grid.is_idle() {
+            end += refresh.max(1);
+            grid.run_until(end)?;
+            stats.drain(event_rx);
+
+            // Home and clear before every frame
+            write!(out, "\x1b[H\x1b[2J{}", self.render_current(grid, &stats))?;
+            out.flush()?;
+            thread::sleep(delay);
+        }
+
+        Ok(stats)
+    }
+
+    fn values(&self, grid: &Grid, stats: &Stats, current: bool) -> Values {
+        let mut values = Values::default();
+        let usage = grid.link_usage();
+
+        match self.metric {
+            Metric::Throughput => {
+                let cycles = (grid.cycle() + 1) as f64;
+                for link in &usage {
+                    let rate = link.counters.flits as f64 / cycles;
+                    values.links.insert((link.from, link.dir), rate);
+                    *values.nodes.entry(link.from).or_default() += rate;
+                }
+            }
+            Metric::Occupancy => {
+                let series = grid.time_series();
+                let sampled = !current && !series.samples.is_empty();
+                let average = |id: BufferId| {
+                    let samples = series.buffer(id)?;
+                    let (total, count) = samples.fold((0, 0), |(t, c), (_, f)| (t + f, c + 1));
+                    Some(total as f64 / count.max(1) as f64)
+                };
+
+                for link in &usage {
+                    let (at, dir) = (link.to, link.dir.opposite());
+                    let flits = if sampled {
+                        average(BufferId {
+                            at,
+                            dir,
+                            kind: BufferKind::Link,
+                        })
+                        .unwrap_or(0.0)
+                    } else {
+                        grid.access_node(at).map_or(0, |node| {
+                            node.link_buffers[dir.index()].iter().map(|b| b.len()).sum()
+                        }) as f64
+                    };
+                    values.links.insert((link.from, link.dir), flits);
+                }
+
+                let (width, height, _) = grid.dimensions();
+                for (x, y) in (0..height).flat_map(|y| (0..width).map(move |x| (x, y))) {
+                    let at = Coord::new(x, y, self.layer);
+                    let flits = if sampled {
+                        series
+                            .buffers
+                            .iter()
+                            .filter(|id| id.at == at && id.kind != BufferKind::Link)
+                            .filter_map(|&id| average(id))
+                            .sum()
+                    } else {
+                        grid.access_node(at).map_or(0, |node| {
+                            node.inner_buffers
+                                .iter()
+                                .flatten()
+                                .map(|b| b.len())
+                                .sum::<usize>()
+                                + node.local_queue.len()
+                        }) as f64
+                    };
+                    values.nodes.insert(at, flits);
+                }
+            }
+            Metric::Latency => values.nodes.extend(stats.node_latency()),
+        }
+
+        values
+    }
+
+    fn draw(&self, grid: &Grid, values: &Values) -> String {
+        let (width, height, _) = grid.dimensions();
+        let in_layer = |at: &Coord| at.z == self.layer;
+        let node_max = max(values.nodes.iter().filter(|(at, _)| in_layer(at)));
+        let link_max = max(values.links.iter().filter(|((at, _), _)| in_layer(at)));
+
+        // Idle nodes are still drawn so the grid keeps its shape
+        let node = |x, y| {
+            let value = values.nodes.get(&Coord::new(x, y, self.layer));
+            self.shade(value.copied().unwrap_or(0.0), node_max)
+                .map_or_else(|| "[]".to_string(), |shade| shade.repeat(2))
+        };
+        let link = |x, y, dir| {
+            let value = values.links.get(&(Coord::new(x, y, self.layer), dir));
+            self.shade(value.copied().unwrap_or(0.0), link_max)
+                .unwrap_or_else(|| " ".to_string())
+        };
+
+        let mut picture = String::new();
+        for y in 0..height {
+            for x in 0..width {
+                picture += &node(x, y);
+                if x + 1 < width {
+                    picture += &format!(
+                        " {}{} ",
+                        link(x, y, Direction::Right),
+                        link(x + 1, y, Direction::Left)
+                    );
+                }
+            }
+            picture += "\n";
+
+            if y + 1 < height {
+                for x in 0..width {
+                    picture += &link(x, y, Direction::Down);
+                    picture += &link(x, y + 1, Direction::Up);
+                    if x + 1 < width {
+                        picture += "    ";
+                    }
+                }
+                picture += "\n";
+            }
+        }
+
+        picture += &format!(
+            "{} on cycle {}, layer {}: nodes up to {:.2}, links up to {:.2}\n",
+            self.metric.name(),
+            grid.cycle(),
+            self.layer,
+            node_max,
+            link_max
+        );
+        picture += &format!("scale: {}\n", SHADES.iter().collect::<String>());
+
+        picture
+    }
+
+    // Character for value relative to max, None when there is nothing
+    fn shade(&self, value: f64, max: f64) -> Option<String> {
+        if value <= 0.0 || max <= 0.0 {
+            return None;
+        }
+
+        let level = ((value / max) * SHADES.len() as f64).ceil() as usize;
+        let level = level.clamp(1, SHADES.len()) - 1;
+        let shade = SHADES[level];
+        if !self.color {
+            return Some(shade.to_string());
+        }
+
+        // Green, yellow then red thirds of the scale
+        let color = match level * 3 / SHADES.len() {
+            0 => 32,
+            1 => 33,
+            _ => 31,
+        };
+        Some(format!("\x1b[{color}m{shade}\x1b[0m"))
+    }
+}
+
+fn max<'a, K: 'a>(values: impl Iterator<Item = (&'a K, &'a f64)>) -> f64 {
+    values.map(|(_, &value)| value).fold(0.0, f64::max)
+}
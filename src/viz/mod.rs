@@ -0,0 +1,3 @@
+pub mod heatmap;
+#[cfg(feature = "tui")]
+pub mod tui;
@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::arch::coord::Coord;
+use crate::arch::grid::Grid;
+use crate::comm::packet::Event;
+use crate::comm::transfer::NodeCommError;
+use crate::sim::engine::Cycle;
+use crate::sim::stats::Stats;
+use crate::viz::heatmap::{Heatmap, Metric, RenderError};
+
+// Events kept in the log, older ones scroll out
+const LOG_LENGTH: usize = 500;
+// How long a frame waits for a key before simulating on
+const FRAME: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct NodeCounters {
+    pub sent: u64,
+    pub received: u64,
+    pub arrived: u64,
+}
+
+// Where a packet that left its source was last seen
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct InFlight {
+    pub at: Coord,
+    pub hops: usize,
+    pub since: Cycle,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Control {
+    // Pauses a running simulation and resumes a paused one
+    Toggle,
+    // Runs a single cycle, only while paused
+    Step,
+    Faster,
+    Slower,
+    // Shades the mesh by the next metric
+    Metric,
+    Quit,
+}
+
+impl Control {
+    pub fn from_key(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::Char(' ') | KeyCode::Char('p') => Some(Control::Toggle),
+            KeyCode::Char('s') | KeyCode::Right => Some(Control::Step),
+            KeyCode::Char('+') | KeyCode::Up => Some(Control::Faster),
+            KeyCode::Char('-') | KeyCode::Down => Some(Control::Slower),
+            KeyCode::Char('m') => Some(Control::Metric),
+            KeyCode::Char('q') | KeyCode::Esc => Some(Control::Quit),
+            _ => None,
+        }
+    }
+}
+
+// Terminal dashboard of a running grid: the mesh shaded by a metric, the packets in flight, per
+// node counters and a scrolling log of the events
+// - Starts paused, every frame then runs speed cycles until paused again
+pub struct Dashboard {
+    event_rx: UnboundedReceiver<Event>,
+    heatmap: Heatmap,
+    stats: Stats,
+    counters: BTreeMap<Coord, NodeCounters>,
+    in_flight: BTreeMap<usize, InFlight>,
+    log: VecDeque<String>,
+    paused: bool,
+    // Cycles simulated per frame while running
+    speed: Cycle,
+}
+
+impl Dashboard {
+    pub fn new(event_rx: UnboundedReceiver<Event>) -> Self {
+        Self {
+            event_rx,
+            heatmap: Heatmap::new(Metric::Occupancy),
+            stats: Stats::new(),
+            counters: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+            log: VecDeque::new(),
+            paused: true,
+            speed: 1,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn counters(&self, pos: impl Into<Coord>) -> NodeCounters {
+        self.counters.get(&pos.into()).copied().unwrap_or_default()
+    }
+
+    pub fn in_flight(&self) -> &BTreeMap<usize, InFlight> {
+        &self.in_flight
+    }
+
+    pub fn log(&self) -> impl Iterator<Item = &String> {
+        self.log.iter()
+    }
+
+    // Heatmap of the grid panel, for picking its layer or colours
+    pub fn heatmap_mut(&mut self) -> &mut Heatmap {
+        &mut self.heatmap
+    }
+
+    // Takes over the terminal until the user quits, returns the statistics of the run
+    pub fn run(mut self, grid: &mut Grid) -> Result<Stats, RenderError> {
+        let mut terminal = ratatui::init();
+        let result = self.run_on(&mut terminal, grid);
+        ratatui::restore();
+
+        result.map(|()| self.stats)
+    }
+
+    fn run_on(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        grid: &mut Grid,
+    ) -> Result<(), RenderError> {
+        loop {
+            terminal.draw(|frame| self.draw(frame, grid))?;
+
+            if event::poll(FRAME)?
+                && let TermEvent::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && let Some(control) = Control::from_key(key.code)
+                && !self.control(grid, control)?
+            {
+                return Ok(());
+            }
+            self.advance(grid)?;
+        }
+    }
+
+    // Applies a control, returns false once the user wants out
+    pub fn control(&mut self, grid: &mut Grid, control: Control) -> Result<bool, NodeCommError> {
+        match control {
+            Control::Toggle => self.paused = !self.paused,
+            Control::Step if self.paused => {
+                grid.step()?;
+                self.collect();
+            }
+            Control::Step => {}
+            Control::Faster => self.speed = (self.speed * 2).min(1 << 16),
+            Control::Slower => self.speed = (self.speed / 2).max(1),
+            Control::Metric => {
+                let next = match self.heatmap.metric() {
+                    Metric::Throughput => Metric::Occupancy,
+                    Metric::Occupancy => Metric::Latency,
+                    Metric::Latency => Metric::Throughput,
+                };
+                self.heatmap.set_metric(next);
+            }
+            Control::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    // Runs the next speed cycles unless paused
+    pub fn advance(&mut self, grid: &mut Grid) -> Result<(), NodeCommError> {
+        if !self.paused && !grid.is_idle() {
+            grid.run_until(grid.cycle() + self.speed)?;
+            self.collect();
+        }
+
+        Ok(())
+    }
+
+    fn collect(&mut self) {
+        while let Ok(event) = self.event_rx.try_recv() {
+            self.record(&event);
+        }
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        self.stats.record(event);
+
+        match *event {
+            Event::PacketSent {
+                id, from, cycle, ..
+            } => {
+                self.counters.entry(from).or_default().sent += 1;
+                self.in_flight.entry(id).or_insert(InFlight {
+                    at: from,
+                    hops: 0,
+                    since: cycle,
+                });
+            }
+            Event::PacketReceived { id, at, .. } => {
+                self.counters.entry(at).or_default().received += 1;
+                if let Some(packet) = self.in_flight.get_mut(&id) {
+                    packet.at = at;
+                    packet.hops += 1;
+                }
+            }
+            Event::PacketArrived { id, at, .. } => {
+                self.counters.entry(at).or_default().arrived += 1;
+                self.in_flight.remove(&id);
+            }
+            _ => {}
+        }
+
+        self.log.push_back(event.to_string());
+        if self.log.len() > LOG_LENGTH {
+            self.log.pop_front();
+        }
+    }
+
+    pub fn draw(&self, frame: &mut Frame, grid: &Grid) {
+        let [top, log, status] = Layout::vertical([
+            Constraint::Min(8),
+            Constraint::Length(10),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [mesh, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(top);
+        let [flying, nodes] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(right);
+
+        let picture = self.heatmap.render_current(grid, &self.stats);
+        frame.render_widget(
+            Paragraph::new(picture).block(Block::default().borders(Borders::ALL).title("Mesh")),
+            mesh,
+        );
+
+        let packets: Vec<ListItem> = self
+            .in_flight
+            .iter()
+            .map(|(id, packet)| {
+                ListItem::new(format!(
+                    "{id}: at {} after {} hops, {} cycles",
+                    packet.at,
+                    packet.hops,
+                    grid.cycle().saturating_sub(packet.since)
+                ))
+            })
+            .collect();
+        let title = format!("Packets in flight ({})", self.in_flight.len());
+        frame.render_widget(
+            List::new(packets).block(Block::default().borders(Borders::ALL).title(title)),
+            flying,
+        );
+
+        let rows = self.counters.iter().map(|(pos, counters)| {
+            Row::new([
+                pos.to_string(),
+                counters.sent.to_string(),
+                counters.received.to_string(),
+                counters.arrived.to_string(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(14),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(8),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(Row::new(["node", "sent", "received", "arrived"]))
+                .block(Block::default().borders(Borders::ALL).title("Nodes")),
+            nodes,
+        );
+
+        // Newest events at the bottom
+        let shown = log.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(shown))
+            .map(|line| Line::from(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Events")),
+            log,
+        );
+
+        let state = match (self.paused, grid.is_idle()) {
+            (_, true) => "done",
+            (true, false) => "paused",
+            (false, false) => "running",
+        };
+        frame.render_widget(
+            Paragraph::new(format!(
+                " cycle {} {state} at {} cycles/frame | space pause/resume  s step  +/- speed  m metric  q quit",
+                grid.cycle(),
+                self.speed
+            )),
+            status,
+        );
+    }
+}